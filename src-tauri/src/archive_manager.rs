@@ -0,0 +1,321 @@
+use crate::structs::JobInfo;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use zip::write::SimpleFileOptions;
+
+/*
+The archive output formats ("zip" and "tar.zst") write a run as numbered volumes in the run folder
+(volume-001.zip, volume-002.zip, ...) instead of mirroring the input folders, which is much faster
+on FAT/exFAT drives than creating thousands of small files.
+- a file goes into a new volume when adding it would take the current volume past the job's
+  archive_volume_size, counting files at their uncompressed size, so only a file larger than
+  the volume size makes a volume larger than it, and that file gets a volume of its own
+- every volume is a complete archive that other tools can open
+- backed up files are addressed as <run folder>/<volume>/<path in archive> in recovery files and manifests
+ */
+
+const VOLUME_PREFIX: &str = "volume-";
+// Room counted for each entry's headers, on top of its contents
+const ENTRY_OVERHEAD: u64 = 1024;
+
+// Gets the output format a job writes runs in. Deduplicated stores always keep files as blobs.
+pub fn get_output_format(job_info: &JobInfo) -> String {
+    if job_info.storage_layout.clone().unwrap_or_default() == "dedup" {
+        return "mirror".to_string();
+    }
+    match job_info.output_format.clone().unwrap_or_default().as_str() {
+        "zip" => "zip".to_string(),
+        "tar.zst" => "tar.zst".to_string(),
+        _ => "mirror".to_string(),
+    }
+}
+
+fn is_volume_name(name: &str) -> bool {
+    name.starts_with(VOLUME_PREFIX) && (name.ends_with(".zip") || name.ends_with(".tar.zst"))
+}
+
+fn is_zip_volume(volume: &Path) -> bool {
+    volume.to_string_lossy().ends_with(".zip")
+}
+
+fn zip_error(e: zip::result::ZipError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+// Splits the path of a backed up file into the archive volume holding it and its path inside the volume.
+// Returns None for files that are not in an archive.
+pub fn split_archive_path(path: &Path) -> Option<(PathBuf, String)> {
+    for ancestor in path.ancestors().skip(1) {
+        let name = ancestor.file_name()?.to_string_lossy().to_string();
+        if is_volume_name(&name) && ancestor.is_file() {
+            let entry_name = path
+                .strip_prefix(ancestor)
+                .ok()?
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy().to_string())
+                .collect::<Vec<String>>()
+                .join("/");
+            return Some((ancestor.to_path_buf(), entry_name));
+        }
+    }
+    None
+}
+
+// Removes the volumes of an earlier run, since runs keeping a single copy reuse their folder
+pub fn remove_volumes(run_dir: &Path) {
+    let entries = match fs::read_dir(run_dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        if is_volume_name(&entry.file_name().to_string_lossy()) {
+            fs::remove_file(entry.path()).unwrap_or_else(|e| {
+                println!("Failed to remove old volume {}: {}", entry.path().display(), e);
+            });
+        }
+    }
+}
+
+// Hashes what is read from a file. When a size is given, reading stops at that size and a file that
+// shrank while it was read is padded with zeros, since tar headers record the size up front.
+// The padding is not hashed, so the archived copy fails verification.
+struct HashingReader<R: Read> {
+    inner: R,
+    hasher: Sha256,
+    remaining: Option<u64>,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let limit = match self.remaining {
+            Some(0) => return Ok(0),
+            Some(remaining) => buffer.len().min(remaining.min(usize::MAX as u64) as usize),
+            None => buffer.len(),
+        };
+        let mut read = self.inner.read(&mut buffer[..limit])?;
+        if read == 0 && self.remaining.is_some() {
+            buffer[..limit].fill(0);
+            read = limit;
+        } else {
+            self.hasher.update(&buffer[..read]);
+        }
+        if let Some(remaining) = self.remaining.as_mut() {
+            *remaining -= read as u64;
+        }
+        Ok(read)
+    }
+}
+
+enum ArchiveWriter {
+    Zip(zip::ZipWriter<fs::File>),
+    TarZst(tar::Builder<zstd::Encoder<'static, fs::File>>),
+}
+
+// Writes files into numbered archive volumes, starting a new volume once one is full
+pub struct VolumeWriter {
+    run_dir: PathBuf,
+    format: String,
+    volume_size: u64,
+    volume_number: u32,
+    volume_path: PathBuf,
+    // The uncompressed size of what has been added to the current volume
+    volume_bytes: u64,
+    writer: Option<ArchiveWriter>,
+}
+
+impl VolumeWriter {
+    // A volume size of 0 writes every file to a single volume
+    pub fn new(run_dir: &Path, format: &str, volume_size: u64) -> VolumeWriter {
+        VolumeWriter {
+            run_dir: run_dir.to_path_buf(),
+            format: format.to_string(),
+            volume_size,
+            volume_number: 0,
+            volume_path: PathBuf::new(),
+            volume_bytes: 0,
+            writer: None,
+        }
+    }
+
+    fn open_volume(&mut self) -> io::Result<()> {
+        self.volume_number += 1;
+        self.volume_path = self
+            .run_dir
+            .join(format!("{}{:03}.{}", VOLUME_PREFIX, self.volume_number, self.format));
+        let file = fs::File::create(&self.volume_path)?;
+        self.volume_bytes = 0;
+        self.writer = Some(if self.format == "zip" {
+            ArchiveWriter::Zip(zip::ZipWriter::new(file))
+        } else {
+            ArchiveWriter::TarZst(tar::Builder::new(zstd::Encoder::new(file, 0)?))
+        });
+        Ok(())
+    }
+
+    fn finish_volume(&mut self) -> io::Result<()> {
+        match self.writer.take() {
+            Some(ArchiveWriter::Zip(zip)) => {
+                zip.finish().map_err(zip_error)?;
+            }
+            Some(ArchiveWriter::TarZst(tar)) => {
+                tar.into_inner()?.finish()?;
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    // Adds an opened file to the current volume, or to a new one if it would not fit in the current one.
+    // Returns the path the file is backed up at and the SHA-256 hash of what was read from it.
+    pub fn add_file(&mut self, file: fs::File, entry_name: &str) -> io::Result<(PathBuf, Vec<u8>)> {
+        let metadata = file.metadata()?;
+        let entry_bytes = metadata.len().saturating_add(ENTRY_OVERHEAD);
+        if self.writer.is_some()
+            && self.volume_size > 0
+            && self.volume_bytes > 0
+            && self.volume_bytes.saturating_add(entry_bytes) > self.volume_size
+        {
+            self.finish_volume()?;
+        }
+        if self.writer.is_none() {
+            self.open_volume()?;
+        }
+        let mut reader = HashingReader {
+            inner: file,
+            hasher: Sha256::new(),
+            remaining: None,
+        };
+
+        match self.writer.as_mut().unwrap() {
+            ArchiveWriter::Zip(zip) => {
+                let options = SimpleFileOptions::default()
+                    .compression_method(zip::CompressionMethod::Deflated)
+                    .large_file(metadata.len() >= u32::MAX as u64);
+                zip.start_file(entry_name, options).map_err(zip_error)?;
+                io::copy(&mut reader, zip)?;
+            }
+            ArchiveWriter::TarZst(tar) => {
+                reader.remaining = Some(metadata.len());
+                let mut header = tar::Header::new_gnu();
+                header.set_metadata(&metadata);
+                tar.append_data(&mut header, entry_name, &mut reader)?;
+            }
+        }
+
+        let output = self.volume_path.join(entry_name);
+        self.volume_bytes = self.volume_bytes.saturating_add(entry_bytes);
+        if self.volume_size > 0 && self.volume_bytes >= self.volume_size {
+            self.finish_volume()?;
+        }
+        Ok((output, reader.hasher.finalize().to_vec()))
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.finish_volume()
+    }
+}
+
+// Calls visit with the path and contents of every file in a volume, in the order they are stored
+fn read_volume<F>(volume: &Path, mut visit: F) -> io::Result<()>
+where
+    F: FnMut(String, &mut dyn Read) -> io::Result<()>,
+{
+    let file = fs::File::open(volume)?;
+    if is_zip_volume(volume) {
+        let mut archive = zip::ZipArchive::new(file).map_err(zip_error)?;
+        for index in 0..archive.len() {
+            let mut entry = archive.by_index(index).map_err(zip_error)?;
+            if entry.is_dir() {
+                continue;
+            }
+            let entry_name = entry.name().to_string();
+            visit(entry_name, &mut entry)?;
+        }
+    } else {
+        let mut archive = tar::Archive::new(zstd::Decoder::new(file)?);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if entry.header().entry_type() != tar::EntryType::Regular {
+                continue;
+            }
+            let entry_name = entry.path()?.to_string_lossy().replace('\\', "/");
+            visit(entry_name, &mut entry)?;
+        }
+    }
+    Ok(())
+}
+
+// Hashes every file in a volume, keyed by its path inside the volume
+pub fn hash_volume(volume: &Path) -> io::Result<HashMap<String, Vec<u8>>> {
+    let mut hashes: HashMap<String, Vec<u8>> = HashMap::new();
+    read_volume(volume, |entry_name, reader| {
+        let mut hasher = Sha256::new();
+        io::copy(reader, &mut hasher)?;
+        hashes.insert(entry_name, hasher.finalize().to_vec());
+        Ok(())
+    })?;
+    Ok(hashes)
+}
+
+// Hashes the backed up files that are in archives, reading each volume once.
+// Files in volumes that cannot be read are left out.
+pub fn hash_archived_files(output_files: &[String]) -> HashMap<String, Vec<u8>> {
+    let mut volumes: HashMap<PathBuf, Vec<(String, String)>> = HashMap::new();
+    for output_file in output_files {
+        if let Some((volume, entry_name)) = split_archive_path(Path::new(output_file)) {
+            volumes
+                .entry(volume)
+                .or_default()
+                .push((entry_name, output_file.clone()));
+        }
+    }
+
+    let mut hashes: HashMap<String, Vec<u8>> = HashMap::new();
+    for (volume, files) in volumes {
+        match hash_volume(&volume) {
+            Ok(mut volume_hashes) => {
+                for (entry_name, output_file) in files {
+                    if let Some(hash) = volume_hashes.remove(&entry_name) {
+                        hashes.insert(output_file, hash);
+                    }
+                }
+            }
+            Err(e) => println!("Failed to read volume {}: {}", volume.display(), e),
+        }
+    }
+    hashes
+}
+
+// Extracts files from a volume to the given destinations, reading the volume once.
+// Returns the result for every destination.
+pub fn extract_files(volume: &Path, files: &HashMap<String, PathBuf>) -> Vec<(PathBuf, Result<(), String>)> {
+    let mut results: Vec<(PathBuf, Result<(), String>)> = Vec::new();
+    let mut extracted: HashSet<String> = HashSet::new();
+    let read_result = read_volume(volume, |entry_name, reader| {
+        if let Some(destination) = files.get(&entry_name) {
+            extracted.insert(entry_name);
+            let result = fs::File::create(destination)
+                .and_then(|mut output| io::copy(reader, &mut output))
+                .map(|_| ())
+                .map_err(|e| e.to_string());
+            results.push((destination.clone(), result));
+        }
+        Ok(())
+    });
+
+    let read_error = read_result
+        .err()
+        .map(|e| format!("Failed to read {}: {}", volume.display(), e));
+    for (entry_name, destination) in files {
+        if !extracted.contains(entry_name) {
+            let error = read_error
+                .clone()
+                .unwrap_or(format!("{} is not in {}", entry_name, volume.display()));
+            results.push((destination.clone(), Err(error)));
+        }
+    }
+    results
+}
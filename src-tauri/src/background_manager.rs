@@ -1,7 +1,7 @@
 use tauri_plugin_notification::NotificationExt;
 use crate::drive_manager::get_drive_uuid;
 use crate::structs::JobInfo;
-use crate::{drive_manager, job_manager, storage_manager, structs};
+use crate::{drive_manager, job_manager, schedule_manager, scrub_manager, storage_manager, structs, watch_manager};
 use time::OffsetDateTime;
 
 pub async fn background_worker() {
@@ -50,7 +50,12 @@ pub async fn background_worker() {
                     println!("Root drive found: {}", root_drive);
 
                     for new_drive in &new_drives {
-                        if new_drive.get(0).unwrap().starts_with(&root_drive) {
+                        // The new drive must also contain the output directory, since paths on
+                        // Linux and macOS fall back to the root mount point while the drive is absent
+                        let new_mount_point = std::path::Path::new(new_drive.get(0).unwrap());
+                        if new_mount_point.starts_with(&root_drive)
+                            && std::path::Path::new(&job.output_dir).starts_with(new_mount_point)
+                        {
                             println!(
                                 "Triggering job {} for new drive {}",
                                 job.clone().job_name,
@@ -129,101 +134,54 @@ pub async fn background_worker() {
             println!("No changes in drive list.");
         }
 
-        // Handle periodic job triggers
+        // Handle scheduled job triggers
         let all_jobs = storage_manager::get_all_jobs();
-        let times_index = vec![
-            "12 AM", "1 AM", "2 AM", "3 AM", "4 AM", "5 AM", "6 AM", "7 AM", "8 AM", "9 AM",
-            "10 AM", "11 AM", "12 PM", "1 PM", "2 PM", "3 PM", "4 PM", "5 PM", "6 PM", "7 PM",
-            "8 PM", "9 PM", "10 PM", "11 PM",
-        ];
-        let weekday_index = vec![
-            "Monday",
-            "Tuesday",
-            "Wednesday",
-            "Thursday",
-            "Friday",
-            "Saturday",
-            "Sunday",
-        ];
-        for job in all_jobs {
-            for trigger in &job.triggers {
-                if trigger.clone().trigger_type == "time" {
-                    let current_time = OffsetDateTime::now_local().unwrap();
-                    let current_hour = current_time.hour();
-                    let current_minute = current_time.minute();
-                    let current_day = current_time.day();
-                    let current_weekday = current_time.weekday();
-                    println!(
-                        "Current time: {}:{} on day {}, weekday {}",
-                        current_hour, current_minute, current_day, current_weekday
-                    );
-                    if trigger.clone().traits.event.unwrap() == "hourly" {
-                        if current_minute == 0 {
-                            println!("Triggering hourly job: {}", job.clone().job_name);
-                            job_manager::start_job(job.clone().uuid);
-                        }
-                    }
-
-                    if trigger.clone().traits.event.unwrap() == "daily" {
-                        let trigger_times = trigger.clone().traits.time.unwrap();
-                        let hour_trigger = trigger_times.get(0);
-                        if let Some(hour_str) = hour_trigger {
-                            if hour_str == &times_index[current_hour as usize]
-                                && current_minute == 0
-                            {
-                                println!("Triggering daily job: {}", job.clone().job_name);
-                                job_manager::start_job(job.clone().uuid);
-                            }
-                        }
-                    }
-
-                    if trigger.clone().traits.event.unwrap() == "weekly" {
-                        let trigger_times = trigger.clone().traits.time.unwrap();
-                        let weekday_trigger = trigger_times.get(0).unwrap();
-                        let time_trigger = trigger_times.get(1);
-
-                        println!(
-                            "Current weekday: {}, Trigger weekday: {:?}",
-                            current_weekday, &weekday_index[current_weekday as usize]
-                        );
-
-                        if weekday_trigger == &weekday_index[current_weekday as usize] {
-                            if let Some(hour_str) = time_trigger {
-                                if hour_str == &times_index[current_hour as usize]
-                                    && current_minute == 0
-                                {
-                                    println!("Triggering weekly job: {}", job.clone().job_name);
-                                    job_manager::start_job(job.clone().uuid);
-                                }
-                            }
-                        }
-                    }
+        let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+        // Runs that cannot start yet, such as while the job is still running, are tried again on the next check
+        for (job, trigger_type) in schedule_manager::get_due_jobs(&all_jobs, now) {
+            let started = if trigger_type == "scrub" {
+                println!("Triggering scheduled scrub of job: {}", job.job_name);
+                scrub_manager::start_scrub(job.uuid.clone())
+            } else {
+                println!("Triggering scheduled job: {}", job.job_name);
+                job_manager::start_job(job.uuid.clone())
+            };
+            if started {
+                schedule_manager::record_run(&job.uuid, &trigger_type, now);
+            }
+        }
+        for (job, trigger_type) in schedule_manager::get_idle_jobs(&all_jobs) {
+            let started = if trigger_type == "scrub" {
+                println!("Running missed scheduled scrub while idle: {}", job.job_name);
+                scrub_manager::start_scrub(job.uuid.clone())
+            } else {
+                println!("Running missed scheduled job while idle: {}", job.job_name);
+                job_manager::start_job(job.uuid.clone())
+            };
+            if started {
+                schedule_manager::record_run(&job.uuid, &trigger_type, now);
+            }
+        }
 
-                    if trigger.clone().traits.event.unwrap() == "monthly" {
-                        let trigger_times = trigger.clone().traits.time.unwrap();
-                        let day_trigger = trigger_times.get(0).unwrap();
-                        let time_trigger = trigger_times.get(1);
-
-                        println!(
-                            "Current weekday: {}, Trigger weekday: {:?}",
-                            current_weekday, &weekday_index[current_weekday as usize]
-                        );
-
-                        if day_trigger.parse::<u8>().unwrap() == current_day {
-                            if let Some(hour_str) = time_trigger {
-                                if hour_str == &times_index[current_hour as usize]
-                                    && current_minute == 0
-                                {
-                                    println!("Triggering monthly job: {}", job.clone().job_name);
-                                    job_manager::start_job(job.clone().uuid);
-                                }
-                            }
-                        }
-                    }
-                }
+        // Handle file watch job triggers once changes have settled
+        watch_manager::sync_watchers(&all_jobs);
+        for job in watch_manager::get_settled_jobs(&all_jobs) {
+            println!("Triggering job after input folder changes: {}", job.job_name);
+            if !job_manager::start_job(job.uuid.clone()) {
+                watch_manager::requeue_change(&job.uuid);
             }
         }
 
-        std::thread::sleep(std::time::Duration::from_secs(60));
+        // Wake up in time for the next scheduled or watch trigger instead of waiting the full minute
+        let poll_interval = std::time::Duration::from_secs(60);
+        let sleep_time = [
+            schedule_manager::get_time_until_next_fire(now),
+            watch_manager::get_time_until_settled(),
+        ]
+        .into_iter()
+        .flatten()
+        .fold(poll_interval, |sleep_time, until_next| sleep_time.min(until_next))
+        .max(std::time::Duration::from_secs(1));
+        std::thread::sleep(sleep_time);
     }
 }
@@ -0,0 +1,171 @@
+use crate::encryption_manager::EncryptionKey;
+use crate::structs::JobInfo;
+use crate::{encryption_manager, settings_manager};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/*
+Copying and verifying run as a pipeline on a pool of worker threads:
+- each worker takes the next file, copies it while hashing the source as it streams through,
+  then hashes the written file and compares the two
+- the source is read once, and many small files are copied at the same time
+- workers stop taking files once one of them fails or the job is paused/stopped
+A file that cannot be copied is tried again up to the job's file_retries, waiting retry_delay milliseconds
+before the first retry and twice as long before each one after it. If it still fails, a job with the
+"continue" error_policy leaves the file out of the run and completes with a warning instead of failing.
+ */
+
+const COPY_BUFFER_SIZE: usize = 1024 * 1024;
+pub const DEFAULT_COPY_WORKERS: u32 = 4;
+const MAX_COPY_WORKERS: u32 = 32;
+const DEFAULT_RETRY_DELAY: u64 = 1000;
+const MAX_FILE_RETRIES: u32 = 10;
+// How often, in milliseconds, a waiting retry checks whether the job was stopped
+const RETRY_SLICE: u64 = 100;
+
+// How a job handles files that cannot be copied
+pub struct ErrorPolicy {
+    pub continue_on_error: bool,
+    pub retries: u32,
+    pub retry_delay: u64,
+}
+
+pub fn get_error_policy(job_info: &JobInfo) -> ErrorPolicy {
+    ErrorPolicy {
+        continue_on_error: job_info.error_policy.clone().unwrap_or_default() == "continue",
+        retries: job_info.file_retries.unwrap_or(0).min(MAX_FILE_RETRIES),
+        retry_delay: job_info.retry_delay.unwrap_or(DEFAULT_RETRY_DELAY),
+    }
+}
+
+// Runs an operation on a file, trying it again after a growing delay while it fails and retries are left.
+// The delay is slept in short slices, and retrying gives up with the last error once should_stop returns true.
+pub fn retry<R, F, S>(error_policy: &ErrorPolicy, mut should_stop: S, mut operation: F) -> Result<R, String>
+where
+    F: FnMut() -> Result<R, String>,
+    S: FnMut() -> bool,
+{
+    let mut delay = error_policy.retry_delay;
+    for _ in 0..error_policy.retries {
+        match operation() {
+            Ok(result) => return Ok(result),
+            Err(message) => {
+                println!("{}, trying again in {} ms", message, delay);
+                let mut remaining = delay;
+                while remaining > 0 {
+                    if should_stop() {
+                        return Err(message);
+                    }
+                    let slice = remaining.min(RETRY_SLICE);
+                    std::thread::sleep(std::time::Duration::from_millis(slice));
+                    remaining -= slice;
+                }
+                if should_stop() {
+                    return Err(message);
+                }
+                delay = delay.saturating_mul(2);
+            }
+        }
+    }
+    operation()
+}
+
+// Gets the number of files to copy at the same time from the settings
+pub fn get_worker_count() -> usize {
+    let workers = settings_manager::get_settings()
+        .copy_workers
+        .unwrap_or(DEFAULT_COPY_WORKERS);
+    workers.clamp(1, MAX_COPY_WORKERS) as usize
+}
+
+// Copies a file, hashing its contents as they are read so the source only needs to be read once.
+// Files are encrypted when a key is given. Returns the SHA-256 hashes of the source and of what was written.
+pub fn copy_file_with_hash(
+    source: &str,
+    destination: &Path,
+    encryption_key: Option<&EncryptionKey>,
+) -> std::io::Result<(Vec<u8>, Vec<u8>)> {
+    let mut input = fs::File::open(source)?;
+    let mut output = fs::File::create(destination)?;
+
+    let hashes = match encryption_key {
+        Some(key) => encryption_manager::encrypt_stream(&mut input, &mut output, key)?,
+        None => {
+            let mut hasher = Sha256::new();
+            let mut buffer = vec![0u8; COPY_BUFFER_SIZE];
+            loop {
+                let read = input.read(&mut buffer)?;
+                if read == 0 {
+                    break;
+                }
+                hasher.update(&buffer[..read]);
+                output.write_all(&buffer[..read])?;
+            }
+            output.flush()?;
+            let hash = hasher.finalize().to_vec();
+            (hash.clone(), hash)
+        }
+    };
+
+    // Keep the permission bits, as std::fs::copy does
+    if let Ok(metadata) = input.metadata() {
+        if let Err(e) = fs::set_permissions(destination, metadata.permissions()) {
+            println!("Failed to copy permissions to {}: {}", destination.display(), e);
+        }
+    }
+
+    Ok(hashes)
+}
+
+// Runs a task for every item on a pool of worker threads, returning the results in the same order as the items.
+// Items are not started once a task fails or should_stop returns true, so their results are None.
+pub fn run_tasks<T, R, F, S>(items: &[T], workers: usize, should_stop: S, task: F) -> Vec<Option<Result<R, String>>>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> Result<R, String> + Sync,
+    S: Fn() -> bool + Sync,
+{
+    let results: Mutex<Vec<Option<Result<R, String>>>> =
+        Mutex::new((0..items.len()).map(|_| None).collect());
+    let next_item = AtomicUsize::new(0);
+    let aborted = AtomicBool::new(false);
+    // Only one worker checks for pause/stop at a time, so the others wait while the job is paused
+    let stop_lock = Mutex::new(());
+
+    let worker_count = workers.clamp(1, items.len().max(1));
+    std::thread::scope(|scope| {
+        for _ in 0..worker_count {
+            scope.spawn(|| loop {
+                let index = next_item.fetch_add(1, Ordering::SeqCst);
+                if index >= items.len() {
+                    break;
+                }
+
+                // The item is left without a result when the pool stops, so callers can tell the job was stopped
+                {
+                    let _guard = stop_lock.lock().unwrap();
+                    if aborted.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    if should_stop() {
+                        aborted.store(true, Ordering::SeqCst);
+                        break;
+                    }
+                }
+
+                let result = task(&items[index]);
+                if result.is_err() {
+                    aborted.store(true, Ordering::SeqCst);
+                }
+                results.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+
+    results.into_inner().unwrap()
+}
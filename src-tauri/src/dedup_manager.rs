@@ -0,0 +1,203 @@
+use crate::encryption_manager::EncryptionKey;
+use crate::{copy_manager, manifest_manager};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static TEMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/*
+Deduplicated store layout (storage_layout: "dedup"):
+archway-<uuid>/
+    blobs/<first two hash characters>/<sha256 hash>
+    snapshots/<timestamp>/manifest.json
+    snapshots/<timestamp>/recovery_paths.json
+Each file is stored once as a blob named by its SHA-256 hash, and each run is a snapshot
+whose manifest and recovery file point at those blobs.
+ */
+
+pub fn get_store_dir(output_dir: &str, job_uuid: &str) -> PathBuf {
+    PathBuf::from(output_dir).join(format!("archway-{}", job_uuid))
+}
+
+// Gets the store folder a snapshot folder belongs to
+pub fn get_store_dir_from_snapshot(snapshot_dir: &Path) -> Option<PathBuf> {
+    let snapshots_dir = snapshot_dir.parent()?;
+    if snapshots_dir.file_name()?.to_string_lossy() != "snapshots" {
+        return None;
+    }
+    Some(snapshots_dir.parent()?.to_path_buf())
+}
+
+// Gets the path of a blob from the hex SHA-256 hash of its contents.
+// Hashes come from manifests on the output drive, so anything else is rejected instead of trusted.
+pub fn get_blob_path(store_dir: &Path, hash: &str) -> Result<PathBuf, String> {
+    if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Invalid file hash: {}", hash));
+    }
+    Ok(store_dir.join("blobs").join(&hash[..2]).join(hash))
+}
+
+pub fn create_snapshot_dir(store_dir: &Path) -> std::io::Result<PathBuf> {
+    let now = time::OffsetDateTime::now_local().unwrap_or_else(|_| time::OffsetDateTime::now_utc());
+    let format_descriptor =
+        time::format_description::parse("[year][month][day]-[hour][minute][second]").unwrap();
+    let snapshot_name = now
+        .format(&format_descriptor)
+        .unwrap_or_else(|_| "unknown".to_string());
+
+    let snapshots_dir = store_dir.join("snapshots");
+    let mut snapshot_dir = snapshots_dir.join(&snapshot_name);
+    let mut suffix = 1;
+    while snapshot_dir.exists() {
+        snapshot_dir = snapshots_dir.join(format!("{}-{}", snapshot_name, suffix));
+        suffix += 1;
+    }
+
+    fs::create_dir_all(&snapshot_dir)?;
+    fs::create_dir_all(store_dir.join("blobs"))?;
+    Ok(snapshot_dir)
+}
+
+// Gets all snapshot folders of a store, oldest first
+pub fn get_snapshot_dirs(store_dir: &Path) -> Vec<PathBuf> {
+    let mut snapshot_dirs: Vec<PathBuf> = Vec::new();
+    if let Ok(entries) = fs::read_dir(store_dir.join("snapshots")) {
+        for entry in entries.flatten() {
+            if entry.file_type().map_or(false, |ft| ft.is_dir()) {
+                snapshot_dirs.push(entry.path());
+            }
+        }
+    }
+    snapshot_dirs.sort();
+    snapshot_dirs
+}
+
+// Gets the newest completed snapshot older than the given snapshot
+pub fn get_previous_snapshot_dir(snapshot_dir: &Path) -> Option<PathBuf> {
+    let store_dir = get_store_dir_from_snapshot(snapshot_dir)?;
+    get_snapshot_dirs(&store_dir)
+        .into_iter()
+        .filter(|dir| dir.as_path() < snapshot_dir)
+        .filter(|dir| manifest_manager::get_manifest_path(dir).exists())
+        .last()
+}
+
+// Removes snapshots left behind by runs that were abandoned before they completed
+pub fn remove_incomplete_snapshots(store_dir: &Path) {
+    for snapshot_dir in get_snapshot_dirs(store_dir) {
+        if manifest_manager::get_manifest_path(&snapshot_dir).exists() {
+            continue;
+        }
+        fs::remove_dir_all(&snapshot_dir).unwrap_or_else(|e| {
+            println!(
+                "Failed to remove incomplete snapshot {}: {}",
+                snapshot_dir.display(),
+                e
+            );
+        });
+    }
+}
+
+// Copies a file into the store, writing to a temporary file first so an
+// interrupted copy never leaves a partial blob under its final name.
+// Files that no longer match the expected hash by the time they are copied are not stored.
+// Returns the hash of the blob as stored, which differs from the file's hash when it is encrypted.
+pub fn store_blob(
+    source: &str,
+    blob_path: &Path,
+    hash: &str,
+    encryption_key: Option<&EncryptionKey>,
+) -> std::io::Result<Vec<u8>> {
+    if let Some(parent) = blob_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // Identical files may be stored by several copy workers at once, so each gets its own temporary file
+    let temp_path = blob_path.with_extension(format!("{}.tmp", TEMP_FILE_COUNTER.fetch_add(1, Ordering::SeqCst)));
+    let (copied_hash, stored_hash) = match copy_manager::copy_file_with_hash(source, &temp_path, encryption_key) {
+        Ok(hashes) => hashes,
+        Err(e) => {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e);
+        }
+    };
+    if manifest_manager::hash_to_hex(&copied_hash) != hash {
+        let _ = fs::remove_file(&temp_path);
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "File changed while it was being copied",
+        ));
+    }
+    fs::rename(&temp_path, blob_path)?;
+    Ok(stored_hash)
+}
+
+// Removes the oldest completed snapshots so only the newest `keep` remain
+pub fn prune_snapshots(store_dir: &Path, keep: u32) -> u32 {
+    let completed: Vec<PathBuf> = get_snapshot_dirs(store_dir)
+        .into_iter()
+        .filter(|dir| manifest_manager::get_manifest_path(dir).exists())
+        .collect();
+
+    let keep = keep.max(1) as usize;
+    if completed.len() <= keep {
+        return 0;
+    }
+
+    let mut removed = 0;
+    for snapshot_dir in &completed[..completed.len() - keep] {
+        match fs::remove_dir_all(snapshot_dir) {
+            Ok(_) => removed += 1,
+            Err(e) => println!(
+                "Failed to remove snapshot {}: {}",
+                snapshot_dir.display(),
+                e
+            ),
+        }
+    }
+    removed
+}
+
+// Removes blobs that are no longer referenced by any snapshot
+pub fn remove_unreferenced_blobs(store_dir: &Path) -> u32 {
+    let mut referenced: HashSet<String> = HashSet::new();
+    for snapshot_dir in get_snapshot_dirs(store_dir) {
+        match manifest_manager::read_manifest(&snapshot_dir) {
+            Some(manifest) => {
+                for entry in manifest.files {
+                    if let Some(hash) = entry.hash {
+                        referenced.insert(hash);
+                    }
+                }
+            }
+            None => {
+                // Without a manifest the blobs used by this snapshot are unknown, so keep everything
+                println!(
+                    "Snapshot has no manifest, skipping blob cleanup: {}",
+                    snapshot_dir.display()
+                );
+                return 0;
+            }
+        }
+    }
+
+    let mut removed = 0;
+    if let Ok(prefix_dirs) = fs::read_dir(store_dir.join("blobs")) {
+        for prefix_dir in prefix_dirs.flatten() {
+            if let Ok(blobs) = fs::read_dir(prefix_dir.path()) {
+                for blob in blobs.flatten() {
+                    let blob_name = blob.file_name().to_string_lossy().to_string();
+                    if referenced.contains(&blob_name) {
+                        continue;
+                    }
+                    match fs::remove_file(blob.path()) {
+                        Ok(_) => removed += 1,
+                        Err(e) => println!("Failed to remove blob {}: {}", blob_name, e),
+                    }
+                }
+            }
+        }
+    }
+    removed
+}
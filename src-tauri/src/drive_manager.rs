@@ -42,6 +42,19 @@ pub fn get_drive_uuid(drive_name: &str) -> String {
     return uuid_string;
 }
 
+// Reads the UUID of a drive without creating an archway.json file if it has none
+pub fn read_drive_uuid(drive_name: &str) -> Option<String> {
+    let uuid_path = Path::new(drive_name).join("archway.json");
+    let file = fs::File::open(&uuid_path).ok()?;
+    match serde_json::from_reader::<_, DriveInfoFile>(file) {
+        Ok(drive_info) => Some(drive_info.uuid),
+        Err(e) => {
+            println!("Failed to read drive info file: {}", e);
+            None
+        }
+    }
+}
+
 pub fn add_job_to_drive(drive_name: &str, job_info: JobInfo) -> bool {
     let uuid_drive = Path::new(drive_name);
     let uuid_path = uuid_drive.join("archway.json");
@@ -99,7 +112,35 @@ pub fn get_root_drive(path: &str) -> Option<String> {
             return Some(prefix_component.as_os_str().to_string_lossy().to_string());
         }
     }
-    None
+
+    // Paths without a drive prefix (Linux, macOS) resolve to the longest mount point containing them
+    let disks = Disks::new_with_refreshed_list();
+    disks
+        .list()
+        .iter()
+        .map(|disk| disk.mount_point())
+        .filter(|mount_point| path.starts_with(mount_point))
+        .max_by_key(|mount_point| mount_point.components().count())
+        .map(|mount_point| mount_point.to_string_lossy().to_string())
+}
+
+// Checks whether a path resolved to the root, home or system volume only because the drive it is on is not connected.
+// On Linux and macOS the mount point of an unplugged drive is just a folder on one of these volumes,
+// so a path under it that does not exist yet would be created there instead of on the drive.
+pub fn is_fallback_drive(path: &str, drive: &str) -> bool {
+    let path = Path::new(path);
+    if path.exists() || matches!(path.components().next(), Some(std::path::Component::Prefix(_))) {
+        return false;
+    }
+
+    let drive = Path::new(drive);
+    if drive == Path::new("/") || drive == Path::new("/System/Volumes/Data") {
+        return true;
+    }
+    std::env::var("HOME")
+        .ok()
+        .and_then(|home| get_root_drive(&home))
+        .is_some_and(|home_drive| Path::new(&home_drive) == drive)
 }
 
 pub fn get_all_drives() -> Vec<Vec<String>> {
@@ -115,3 +156,17 @@ pub fn get_all_drives() -> Vec<Vec<String>> {
 
     return drive_list;
 }
+
+// Gets the available space in bytes on the drive mounted at the given root drive
+pub fn get_available_space(drive_name: &str) -> Option<u64> {
+    let disks = Disks::new_with_refreshed_list();
+    let drive_name = drive_name.trim_end_matches(['\\', '/']);
+    disks
+        .list()
+        .iter()
+        .find(|disk| {
+            let mount_point = disk.mount_point().to_string_lossy().to_string();
+            mount_point.trim_end_matches(['\\', '/']) == drive_name
+        })
+        .map(|disk| disk.available_space())
+}
@@ -0,0 +1,507 @@
+use crate::manifest_manager::{hash_to_hex, hex_to_bytes};
+use crate::storage_manager::{file_with_executable, read_json_file, write_json_file};
+use crate::structs::{JobInfo, RunEncryption};
+use crate::{drive_manager, manifest_manager, snapshot_manager, storage_manager};
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::stream::{DecryptorBE32, EncryptorBE32};
+use chacha20poly1305::aead::{KeyInit, OsRng};
+use chacha20poly1305::XChaCha20Poly1305;
+use sha2::{Digest, Sha256};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+/*
+Encrypted jobs (encryption: "passphrase" or "keyfile") write every output file as:
+    ARCHENC1 | salt length | salt | 19 byte nonce | chunks of up to 64 KiB, each sealed with XChaCha20-Poly1305
+The chunks use the STREAM construction, so reordered, truncated or changed chunks fail to decrypt.
+The key is derived with Argon2id from the passphrase or the SHA-256 hash of the keyfile, salted with
+the job's encryption_salt. A new salt is created whenever the passphrase, keyfile or method changes,
+so every key has its own salt and keys of earlier runs are kept alongside the current one.
+Each run folder gets an unencrypted encryption.json naming the job and salt, so a restore knows which key it needs,
+and files written under an older salt than the job's current one are written again instead of being reused.
+Passphrases are never saved. Derived keys are kept in memory until Archway closes, so after a restart the
+passphrase has to be entered again before the job can run. Jobs with remember_key also save their keys in
+job_keys.json next to the executable, so scheduled runs can encrypt without asking. That file holds the raw
+keys: anyone who can read it can decrypt the backups without the passphrase. Keys are never saved when Archway
+runs from the drive holding the backups, since the key would travel with the data it protects.
+ */
+
+const MAGIC: &[u8; 8] = b"ARCHENC1";
+const NONCE_SIZE: usize = 19;
+const CHUNK_SIZE: usize = 64 * 1024;
+const TAG_SIZE: usize = 16;
+
+// A key, and the salt it was derived with, which is recorded in every file it encrypts
+#[derive(Clone)]
+pub struct EncryptionKey {
+    key: [u8; 32],
+    salt: String,
+}
+
+impl EncryptionKey {
+    pub fn salt(&self) -> &str {
+        &self.salt
+    }
+}
+
+// Keys unlocked or derived since Archway started, including those that are not saved
+static SESSION_KEYS: Lazy<Mutex<HashMap<String, EncryptionKey>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn get_encryption_path(run_dir: &Path) -> std::path::PathBuf {
+    run_dir.join("encryption.json")
+}
+
+fn get_saved_keys() -> HashMap<String, String> {
+    read_json_file::<HashMap<String, String>>(file_with_executable("job_keys.json"))
+        .unwrap_or_else(|_| HashMap::new())
+}
+
+fn get_key_name(job_uuid: &str, salt: &str) -> String {
+    format!("{}|{}", job_uuid, salt)
+}
+
+fn parse_saved_key(key_hex: &str, salt: &str) -> Option<EncryptionKey> {
+    Some(EncryptionKey {
+        key: hex_to_bytes(key_hex)?.try_into().ok()?,
+        salt: salt.to_string(),
+    })
+}
+
+// Checks whether Archway runs from a removable drive that also holds the given folder, such as a
+// portable install on the backup drive. Keys saved there would sit next to the files they protect.
+fn is_executable_on_drive(path: &str) -> bool {
+    let executable_drive = drive_manager::get_root_drive(&file_with_executable(""));
+    let home_drive = std::env::var("HOME")
+        .or_else(|_| std::env::var("USERPROFILE"))
+        .ok()
+        .and_then(|home| drive_manager::get_root_drive(&home));
+    executable_drive.is_some()
+        && executable_drive == drive_manager::get_root_drive(path)
+        && executable_drive != home_drive
+}
+
+// Checks whether a job saves its keys on this computer rather than only keeping them until Archway closes
+fn remembers_key(job_uuid: &str) -> bool {
+    storage_manager::get_all_jobs()
+        .into_iter()
+        .find(|job| job.uuid == job_uuid)
+        .and_then(|job| job.remember_key)
+        .unwrap_or(false)
+}
+
+// Keeps a key for this session, and when remember is set saves it on this computer,
+// unless Archway runs from the drive at backup_path. Returns false if a key to remember could not be saved.
+fn save_key(job_uuid: &str, key: &EncryptionKey, backup_path: &str, remember: bool) -> bool {
+    SESSION_KEYS
+        .lock()
+        .unwrap()
+        .insert(get_key_name(job_uuid, &key.salt), key.clone());
+    if !remember {
+        return true;
+    }
+    if is_executable_on_drive(backup_path) {
+        println!("Archway runs from the backup drive, so the key is only kept until Archway closes");
+        return false;
+    }
+    let mut keys = get_saved_keys();
+    keys.insert(get_key_name(job_uuid, &key.salt), hash_to_hex(&key.key));
+    write_json_file(file_with_executable("job_keys.json"), &keys).is_ok()
+}
+
+// Removes the saved keys of a job from this computer, keeping them for this session
+pub fn forget_saved_keys(job_uuid: &str) -> bool {
+    let prefix = format!("{}|", job_uuid);
+    let mut keys = get_saved_keys();
+    let key_count = keys.len();
+    keys.retain(|key_name, _| !key_name.starts_with(&prefix));
+    key_count == keys.len() || write_json_file(file_with_executable("job_keys.json"), &keys).is_ok()
+}
+
+fn get_saved_key(job_uuid: &str, salt: &str) -> Option<EncryptionKey> {
+    let key_name = get_key_name(job_uuid, salt);
+    if let Some(key) = SESSION_KEYS.lock().unwrap().get(&key_name) {
+        return Some(key.clone());
+    }
+    parse_saved_key(get_saved_keys().get(&key_name)?, salt)
+}
+
+// Finds a key of any job by its salt, since salts are random and never shared
+fn find_key_by_salt(salt: &str) -> Option<EncryptionKey> {
+    let suffix = format!("|{}", salt);
+    if let Some(key) = SESSION_KEYS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(key_name, _)| key_name.ends_with(&suffix))
+        .map(|(_, key)| key.clone())
+    {
+        return Some(key);
+    }
+    get_saved_keys()
+        .iter()
+        .find(|(key_name, _)| key_name.ends_with(&suffix))
+        .and_then(|(_, key_hex)| parse_saved_key(key_hex, salt))
+}
+
+// Creates a random salt for a job's key, as hex
+pub fn create_salt() -> String {
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    hash_to_hex(&salt)
+}
+
+fn derive_key(secret: &[u8], salt: &str) -> Result<EncryptionKey, String> {
+    let salt_bytes = hex_to_bytes(salt)
+        .filter(|salt_bytes| salt_bytes.len() <= u8::MAX as usize)
+        .ok_or(String::from("Invalid encryption salt"))?;
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(secret, &salt_bytes, &mut key)
+        .map_err(|e| format!("Failed to derive key: {}", e))?;
+    Ok(EncryptionKey {
+        key,
+        salt: salt.to_string(),
+    })
+}
+
+// Keyfiles can be any file, so they are hashed before key derivation
+fn read_keyfile(keyfile: &str) -> Result<Vec<u8>, String> {
+    let contents = fs::read(keyfile).map_err(|e| format!("Failed to read keyfile {}: {}", keyfile, e))?;
+    Ok(Sha256::digest(&contents).to_vec())
+}
+
+// Gets the key a job's backups under its current salt were encrypted with, if the passphrase opens one of them
+fn check_current_passphrase(job_uuid: &str, passphrase: &str) -> Option<EncryptionKey> {
+    let job_info = storage_manager::get_all_jobs().into_iter().find(|job| job.uuid == job_uuid)?;
+    let salt = job_info.encryption_salt.clone()?;
+    let run_dir = snapshot_manager::get_job_snapshots(&job_info)
+        .into_iter()
+        .map(|snapshot| std::path::PathBuf::from(snapshot.path))
+        .find(|run_dir| read_run_encryption(run_dir).is_some_and(|encryption| encryption.salt == salt))?;
+    let key = derive_key(passphrase.as_bytes(), &salt).ok()?;
+    decrypt_to_bytes(&run_dir.join("recovery_paths.json"), &key).ok()?;
+    Some(key)
+}
+
+// Derives a job's key from a passphrase and keeps it for this session, also saving it when remember_key is set.
+// Entering the passphrase a job's backups were last written with keeps the job's salt, so the backups are not
+// written again. Any other passphrase gets a new salt, so keys of earlier runs are kept.
+// Returns the salt, which becomes the job's encryption_salt.
+pub fn set_job_passphrase(job_uuid: &str, passphrase: &str, output_dir: &str, remember_key: bool) -> Result<String, String> {
+    if passphrase.is_empty() {
+        return Err(String::from("The passphrase is empty"));
+    }
+    if remember_key && is_executable_on_drive(output_dir) {
+        return Err(String::from(
+            "Archway is running from the job's output drive, so the key would be saved next to the backups it protects. Turn off remembering the key, or run Archway from another drive.",
+        ));
+    }
+    let key = match check_current_passphrase(job_uuid, passphrase) {
+        Some(key) => key,
+        None => derive_key(passphrase.as_bytes(), &create_salt())?,
+    };
+    if !save_key(job_uuid, &key, output_dir, remember_key) {
+        return Err(String::from("Failed to save the key"));
+    }
+    Ok(key.salt.clone())
+}
+
+// Gets the key a job encrypts its files with, or None if the job is not encrypted
+pub fn get_job_key(job_info: &JobInfo) -> Result<Option<EncryptionKey>, String> {
+    let method = job_info.encryption.clone().unwrap_or("off".to_string());
+    if method == "off" {
+        return Ok(None);
+    }
+    let salt = job_info
+        .encryption_salt
+        .clone()
+        .ok_or(String::from("The job has no encryption salt"))?;
+
+    match method.as_str() {
+        "passphrase" => get_saved_key(&job_info.uuid, &salt)
+            .map(Some)
+            .ok_or(String::from("The job's passphrase has not been entered since Archway started. Enter it in the job's settings.")),
+        "keyfile" => {
+            let keyfile = job_info
+                .encryption_keyfile
+                .clone()
+                .ok_or(String::from("The job has no keyfile"))?;
+            derive_key(&read_keyfile(&keyfile)?, &salt).map(Some)
+        }
+        _ => Err(format!("Unknown encryption method: {}", method)),
+    }
+}
+
+// Records which job and salt a run's files were encrypted with
+pub fn write_run_encryption(run_dir: &Path, job_info: &JobInfo) -> bool {
+    let encryption = RunEncryption {
+        job_uuid: job_info.uuid.clone(),
+        method: job_info.encryption.clone().unwrap_or_default(),
+        salt: job_info.encryption_salt.clone().unwrap_or_default(),
+    };
+    write_json_file(get_encryption_path(run_dir).to_string_lossy().to_string(), &encryption).is_ok()
+}
+
+fn read_run_encryption(run_dir: &Path) -> Option<RunEncryption> {
+    read_json_file::<RunEncryption>(get_encryption_path(run_dir).to_string_lossy().to_string()).ok()
+}
+
+// Removes encryption.json left in a reused run folder by an earlier encrypted run
+pub fn remove_run_encryption(run_dir: &Path) {
+    let encryption_path = get_encryption_path(run_dir);
+    if encryption_path.exists() {
+        fs::remove_file(&encryption_path).unwrap_or_else(|e| {
+            println!("Failed to remove {}: {}", encryption_path.display(), e);
+        });
+    }
+}
+
+pub fn is_run_encrypted(run_dir: &Path) -> bool {
+    get_encryption_path(run_dir).exists()
+}
+
+// Gets the key a run encrypts its manifest and journal with, or None if the run is not encrypted
+pub fn get_encrypted_run_key(run_dir: &Path) -> Result<Option<EncryptionKey>, String> {
+    if !is_run_encrypted(run_dir) {
+        return Ok(None);
+    }
+    get_run_key(run_dir)?.map(Some).ok_or(String::from("The backup's key is not available"))
+}
+
+// Gets the key to read a run's encrypted files with.
+// Runs without encryption.json may still hold encrypted blobs from a deduplicated store, so the job's own key is used.
+pub fn get_run_key(run_dir: &Path) -> Result<Option<EncryptionKey>, String> {
+    let encryption = match read_run_encryption(run_dir) {
+        Some(encryption) => encryption,
+        None => {
+            return match manifest_manager::read_manifest(run_dir) {
+                Some(manifest) => storage_manager::get_all_jobs()
+                    .into_iter()
+                    .find(|job| job.uuid == manifest.job_uuid)
+                    .map_or(Ok(None), |job| get_job_key(&job)),
+                None => Ok(None),
+            };
+        }
+    };
+
+    if let Some(key) = get_saved_key(&encryption.job_uuid, &encryption.salt) {
+        return Ok(Some(key));
+    }
+    // Keyfile jobs on this computer can derive the key again, also for salts the job used before
+    if let Some(keyfile) = storage_manager::get_all_jobs()
+        .into_iter()
+        .find(|job| job.uuid == encryption.job_uuid && job.encryption.as_deref() == Some("keyfile"))
+        .and_then(|job| job.encryption_keyfile)
+    {
+        if let Ok(key) = read_keyfile(&keyfile).and_then(|secret| derive_key(&secret, &encryption.salt)) {
+            SESSION_KEYS
+                .lock()
+                .unwrap()
+                .insert(get_key_name(&encryption.job_uuid, &encryption.salt), key.clone());
+            return Ok(Some(key));
+        }
+    }
+    Err(String::from("This backup is encrypted. Unlock it with its passphrase or keyfile to restore it."))
+}
+
+// Derives a run's key from a passphrase or keyfile, checks it against the run's recovery file and saves it.
+// Every salt has its own key, so this never replaces the key the job currently encrypts with.
+pub fn unlock_run(run_dir: &Path, passphrase: Option<String>, keyfile: Option<String>) -> Result<(), String> {
+    let encryption = read_run_encryption(run_dir).ok_or(String::from("This backup is not encrypted"))?;
+    let secret = match (passphrase, keyfile) {
+        (Some(passphrase), _) if !passphrase.is_empty() => passphrase.into_bytes(),
+        (_, Some(keyfile)) => read_keyfile(&keyfile)?,
+        _ => return Err(String::from("Enter a passphrase or choose a keyfile")),
+    };
+    let key = derive_key(&secret, &encryption.salt)?;
+
+    decrypt_to_bytes(&run_dir.join("recovery_paths.json"), &key)
+        .map_err(|_| String::from("The passphrase or keyfile is not correct for this backup"))?;
+    if get_saved_key(&encryption.job_uuid, &encryption.salt).is_none() {
+        save_key(&encryption.job_uuid, &key, &run_dir.to_string_lossy(), remembers_key(&encryption.job_uuid));
+    }
+    Ok(())
+}
+
+// Checks whether a file starts with the encrypted file header
+pub fn is_encrypted(path: &Path) -> bool {
+    let mut header = [0u8; 8];
+    match fs::File::open(path) {
+        Ok(mut file) => file.read_exact(&mut header).is_ok() && &header == MAGIC,
+        Err(_) => false,
+    }
+}
+
+// Reads the header of an encrypted file, returning the salt of its key and its nonce
+fn read_header<R: Read>(reader: &mut R) -> io::Result<(String, [u8; NONCE_SIZE])> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "The file is not encrypted"));
+    }
+    let mut salt_length = [0u8; 1];
+    reader.read_exact(&mut salt_length)?;
+    let mut salt = vec![0u8; salt_length[0] as usize];
+    reader.read_exact(&mut salt)?;
+    let salt = hash_to_hex(&salt);
+    let mut nonce = [0u8; NONCE_SIZE];
+    reader.read_exact(&mut nonce)?;
+    Ok((salt, nonce))
+}
+
+// Checks whether a stored file is stored the way a run with the given key stores files:
+// unencrypted when there is no key, otherwise encrypted under the key's salt
+pub fn is_stored_with(path: &Path, encryption_key: Option<&EncryptionKey>) -> bool {
+    match encryption_key {
+        None => !is_encrypted(path),
+        Some(key) => fs::File::open(path)
+            .and_then(|mut file| read_header(&mut file))
+            .map_or(false, |(salt, _)| salt == key.salt()),
+    }
+}
+
+// Gets the key to decrypt a file with. Blobs shared between snapshots may have been written again
+// under a newer salt than the run's, so the key of the file's own salt is used when there is one.
+pub fn get_file_key(path: &Path, run_key: &EncryptionKey) -> EncryptionKey {
+    let file_salt = fs::File::open(path)
+        .and_then(|mut file| read_header(&mut file))
+        .ok()
+        .map(|(salt, _)| salt);
+    match file_salt {
+        Some(salt) if salt != run_key.salt => find_key_by_salt(&salt).unwrap_or(run_key.clone()),
+        _ => run_key.clone(),
+    }
+}
+
+fn crypto_error() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Encrypted data is damaged or the key is wrong")
+}
+
+// Reads until the buffer is full or the reader ends, returning the number of bytes read
+fn read_chunk<R: Read>(reader: &mut R, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        let read = reader.read(&mut buffer[filled..])?;
+        if read == 0 {
+            break;
+        }
+        filled += read;
+    }
+    Ok(filled)
+}
+
+// Encrypts everything from a reader into a writer.
+// Returns the SHA-256 hashes of the plaintext and of the encrypted output.
+pub fn encrypt_stream<R: Read, W: Write>(reader: &mut R, writer: &mut W, key: &EncryptionKey) -> io::Result<(Vec<u8>, Vec<u8>)> {
+    let mut nonce = [0u8; NONCE_SIZE];
+    OsRng.fill_bytes(&mut nonce);
+    let cipher = XChaCha20Poly1305::new((&key.key).into());
+    let mut encryptor = EncryptorBE32::from_aead(cipher, (&nonce).into());
+    let mut plain_hasher = Sha256::new();
+    let mut stored_hasher = Sha256::new();
+
+    // Salts are checked to fit in a byte when keys are derived
+    let salt = hex_to_bytes(&key.salt).unwrap_or_default();
+    let mut header: Vec<u8> = MAGIC.to_vec();
+    header.push(salt.len() as u8);
+    header.extend_from_slice(&salt);
+    header.extend_from_slice(&nonce);
+    writer.write_all(&header)?;
+    stored_hasher.update(&header);
+
+    // One chunk is read ahead so the last chunk can be sealed as the last
+    let mut chunk = vec![0u8; CHUNK_SIZE];
+    let mut next_chunk = vec![0u8; CHUNK_SIZE];
+    let mut chunk_len = read_chunk(reader, &mut chunk)?;
+    loop {
+        let next_len = read_chunk(reader, &mut next_chunk)?;
+        plain_hasher.update(&chunk[..chunk_len]);
+        if next_len == 0 {
+            let sealed = encryptor.encrypt_last(&chunk[..chunk_len]).map_err(|_| crypto_error())?;
+            stored_hasher.update(&sealed);
+            writer.write_all(&sealed)?;
+            break;
+        }
+        let sealed = encryptor.encrypt_next(&chunk[..chunk_len]).map_err(|_| crypto_error())?;
+        stored_hasher.update(&sealed);
+        writer.write_all(&sealed)?;
+        std::mem::swap(&mut chunk, &mut next_chunk);
+        chunk_len = next_len;
+    }
+    writer.flush()?;
+
+    Ok((plain_hasher.finalize().to_vec(), stored_hasher.finalize().to_vec()))
+}
+
+// Decrypts an encrypted file into a writer, returning the SHA-256 hash of the plaintext
+pub fn decrypt_stream<R: Read, W: Write>(reader: &mut R, writer: &mut W, key: &EncryptionKey) -> io::Result<Vec<u8>> {
+    let (_, nonce) = read_header(reader)?;
+    let cipher = XChaCha20Poly1305::new((&key.key).into());
+    let mut decryptor = DecryptorBE32::from_aead(cipher, (&nonce).into());
+    let mut plain_hasher = Sha256::new();
+
+    let mut chunk = vec![0u8; CHUNK_SIZE + TAG_SIZE];
+    let mut next_chunk = vec![0u8; CHUNK_SIZE + TAG_SIZE];
+    let mut chunk_len = read_chunk(reader, &mut chunk)?;
+    loop {
+        let next_len = read_chunk(reader, &mut next_chunk)?;
+        if next_len == 0 {
+            let plaintext = decryptor.decrypt_last(&chunk[..chunk_len]).map_err(|_| crypto_error())?;
+            plain_hasher.update(&plaintext);
+            writer.write_all(&plaintext)?;
+            break;
+        }
+        let plaintext = decryptor.decrypt_next(&chunk[..chunk_len]).map_err(|_| crypto_error())?;
+        plain_hasher.update(&plaintext);
+        writer.write_all(&plaintext)?;
+        std::mem::swap(&mut chunk, &mut next_chunk);
+        chunk_len = next_len;
+    }
+    writer.flush()?;
+
+    Ok(plain_hasher.finalize().to_vec())
+}
+
+// Decrypts a file to check it, returning the SHA-256 hash of its plaintext
+pub fn hash_decrypted(path: &Path, key: &EncryptionKey) -> io::Result<Vec<u8>> {
+    let mut file = fs::File::open(path)?;
+    decrypt_stream(&mut file, &mut io::sink(), key)
+}
+
+pub fn decrypt_file(source: &Path, destination: &Path, key: &EncryptionKey) -> io::Result<Vec<u8>> {
+    let mut input = fs::File::open(source)?;
+    let mut output = fs::File::create(destination)?;
+    decrypt_stream(&mut input, &mut output, key)
+}
+
+pub fn encrypt_bytes_to_file(path: &Path, data: &[u8], key: &EncryptionKey) -> io::Result<()> {
+    let mut output = fs::File::create(path)?;
+    encrypt_stream(&mut &data[..], &mut output, key)?;
+    Ok(())
+}
+
+// Encrypts a line of text into a hex line, for logs that are appended to one line at a time
+pub fn encrypt_line(line: &str, key: &EncryptionKey) -> io::Result<String> {
+    let mut data: Vec<u8> = Vec::new();
+    encrypt_stream(&mut line.as_bytes(), &mut data, key)?;
+    Ok(hash_to_hex(&data))
+}
+
+pub fn decrypt_line(line: &str, key: &EncryptionKey) -> Option<String> {
+    let data = hex_to_bytes(line)?;
+    let mut plain: Vec<u8> = Vec::new();
+    decrypt_stream(&mut &data[..], &mut plain, key).ok()?;
+    String::from_utf8(plain).ok()
+}
+
+pub fn decrypt_to_bytes(path: &Path, key: &EncryptionKey) -> io::Result<Vec<u8>> {
+    let mut input = fs::File::open(path)?;
+    let mut data: Vec<u8> = Vec::new();
+    decrypt_stream(&mut input, &mut data, key)?;
+    Ok(data)
+}
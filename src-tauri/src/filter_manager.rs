@@ -0,0 +1,687 @@
+use crate::structs::{FileFilter, FilterExpression, JobInfo};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use regex::RegexSet;
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// A filter with its patterns and extension lists prepared, so it can be checked against many files
+pub enum CompiledFilter {
+    Extension(Vec<String>),
+    Size(Option<u64>, Option<u64>),
+    LastUsed(String),
+    Time(String, Option<u64>, Option<u64>),
+    Glob(GlobSet, bool),
+    Regex(RegexSet),
+}
+
+// A filter expression with all of its filters compiled
+pub enum CompiledExpression {
+    All(Vec<CompiledExpression>),
+    Any(Vec<CompiledExpression>),
+    Not(Box<CompiledExpression>),
+    Filter(CompiledFilter),
+}
+
+// Gets the path of a file relative to the input folder containing it, using '/' as the separator.
+// Files outside every input folder keep their full path.
+pub fn get_relative_path(file: &str, input_dirs: &[String]) -> String {
+    let file_path = Path::new(file);
+    let relative_path = input_dirs
+        .iter()
+        .map(Path::new)
+        .filter(|input_dir| file_path.starts_with(input_dir))
+        .max_by_key(|input_dir| input_dir.components().count())
+        .and_then(|input_dir| file_path.strip_prefix(input_dir).ok())
+        .unwrap_or(file_path);
+
+    relative_path
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy().to_string())
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+// Parses a size such as "500 MB", "1.5 GiB" or "4096" (bytes) into bytes.
+// KB, MB, GB and TB are decimal (1000) while KiB, MiB, GiB and TiB are binary (1024).
+pub fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let split_index = size
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split_index);
+
+    let number = number
+        .parse::<f64>()
+        .map_err(|_| format!("Invalid size '{}'", size))?;
+    let multiplier: u64 = match unit.trim().to_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1000,
+        "mb" => 1000 * 1000,
+        "gb" => 1000 * 1000 * 1000,
+        "tb" => 1000 * 1000 * 1000 * 1000,
+        "kib" => 1024,
+        "mib" => 1024 * 1024,
+        "gib" => 1024 * 1024 * 1024,
+        "tib" => 1024 * 1024 * 1024 * 1024,
+        _ => return Err(format!("Unknown size unit '{}'", unit.trim())),
+    };
+
+    let bytes = (number * multiplier as f64).round();
+    if bytes >= u64::MAX as f64 {
+        return Err(format!("Size '{}' is too large", size));
+    }
+    Ok(bytes as u64)
+}
+
+fn parse_optional_size(size: &Option<String>) -> Result<Option<u64>, String> {
+    match size {
+        Some(size) if !size.trim().is_empty() => parse_size(size).map(Some),
+        _ => Ok(None),
+    }
+}
+
+// Gets the last access time of a file in seconds since UNIX_EPOCH
+fn get_last_access_time(path: &str) -> std::io::Result<u64> {
+    let metadata = fs::metadata(path)?;
+    let atime = metadata.accessed()?;
+    let duration = atime.duration_since(UNIX_EPOCH).unwrap();
+    Ok(duration.as_secs())
+}
+
+// Gets the access ("last-used"), modification ("modified") or creation ("created") time
+// of a file in seconds since UNIX_EPOCH
+fn get_file_time(path: &str, time_type: &str) -> std::io::Result<u64> {
+    let metadata = fs::metadata(path)?;
+    let file_time = match time_type {
+        "modified" => metadata.modified()?,
+        "created" => metadata.created()?,
+        _ => metadata.accessed()?,
+    };
+    Ok(file_time
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0))
+}
+
+// Parses a duration such as "45d", "18h" or "1w2d" into seconds.
+// Units are s (seconds), m (minutes), h (hours), d (days), w (weeks) and y (365 days).
+pub fn parse_duration(duration: &str) -> Result<u64, String> {
+    let mut total: u64 = 0;
+    let mut number = String::new();
+    let mut found_unit = false;
+
+    for c in duration.trim().chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        if c.is_whitespace() {
+            continue;
+        }
+        let unit_seconds: u64 = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            'y' => 365 * 24 * 60 * 60,
+            _ => return Err(format!("Unknown duration unit '{}' in '{}'", c, duration)),
+        };
+        let too_long = || format!("Duration '{}' is too long", duration);
+        let value = number
+            .parse::<u64>()
+            .map_err(|_| format!("Invalid duration '{}'", duration))?;
+        total = value
+            .checked_mul(unit_seconds)
+            .and_then(|seconds| total.checked_add(seconds))
+            .ok_or_else(too_long)?;
+        number.clear();
+        found_unit = true;
+    }
+
+    if !number.is_empty() || !found_unit {
+        return Err(format!(
+            "Invalid duration '{}', expected a number followed by a unit such as '45d'",
+            duration
+        ));
+    }
+    Ok(total)
+}
+
+// Parses a local date such as "2024-06-01" or a date and time such as "2024-06-01 18:30"
+// into seconds since UNIX_EPOCH
+pub fn parse_date(date: &str) -> Result<u64, String> {
+    let invalid = || format!("Invalid date '{}', expected YYYY-MM-DD or YYYY-MM-DD HH:MM", date);
+    let date = date.trim();
+    let (date_part, time_part) = match date.split_once(|c| c == ' ' || c == 'T') {
+        Some((date_part, time_part)) => (date_part, Some(time_part.trim())),
+        None => (date, None),
+    };
+
+    let date_fields: Vec<&str> = date_part.split('-').collect();
+    if date_fields.len() != 3 {
+        return Err(invalid());
+    }
+    let year = date_fields[0].parse::<i32>().map_err(|_| invalid())?;
+    let month = date_fields[1].parse::<u8>().map_err(|_| invalid())?;
+    let day = date_fields[2].parse::<u8>().map_err(|_| invalid())?;
+    let month = time::Month::try_from(month).map_err(|_| invalid())?;
+    let calendar_date = time::Date::from_calendar_date(year, month, day).map_err(|_| invalid())?;
+
+    let time_of_day = match time_part {
+        Some(time_part) => {
+            let time_fields: Vec<&str> = time_part.split(':').collect();
+            if time_fields.len() < 2 || time_fields.len() > 3 {
+                return Err(invalid());
+            }
+            let hour = time_fields[0].parse::<u8>().map_err(|_| invalid())?;
+            let minute = time_fields[1].parse::<u8>().map_err(|_| invalid())?;
+            let second = match time_fields.get(2) {
+                Some(second) => second.parse::<u8>().map_err(|_| invalid())?,
+                None => 0,
+            };
+            time::Time::from_hms(hour, minute, second).map_err(|_| invalid())?
+        }
+        None => time::Time::MIDNIGHT,
+    };
+
+    let offset = time::UtcOffset::current_local_offset().unwrap_or(time::UtcOffset::UTC);
+    let timestamp = time::PrimitiveDateTime::new(calendar_date, time_of_day)
+        .assume_offset(offset)
+        .unix_timestamp();
+    Ok(timestamp.max(0) as u64)
+}
+
+// Gets the range of file times (seconds since UNIX_EPOCH) a time filter keeps.
+// A duration keeps files older than it, or newer with the "newer" direction,
+// and after/before dates narrow the range further.
+fn get_time_range(filter: &FileFilter) -> Result<(Option<u64>, Option<u64>), String> {
+    let mut earliest: Option<u64> = None;
+    let mut latest: Option<u64> = None;
+
+    if let Some(duration) = filter.traits.duration.as_ref().filter(|d| !d.trim().is_empty()) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        let threshold = now.saturating_sub(parse_duration(duration)?);
+        match filter.traits.direction.clone().unwrap_or("older".to_string()).as_str() {
+            "newer" => earliest = Some(threshold),
+            _ => latest = Some(threshold),
+        }
+    }
+    if let Some(after) = filter.traits.after.as_ref().filter(|d| !d.trim().is_empty()) {
+        let after = parse_date(after)?;
+        earliest = Some(earliest.map_or(after, |earliest| earliest.max(after)));
+    }
+    if let Some(before) = filter.traits.before.as_ref().filter(|d| !d.trim().is_empty()) {
+        let before = parse_date(before)?;
+        latest = Some(latest.map_or(before, |latest| latest.min(before)));
+    }
+
+    Ok((earliest, latest))
+}
+
+// Checks if a file's last access time is older than a specified period
+fn check_older_than(time: u64, period: &str) -> bool {
+    let now = SystemTime::now();
+    let period_duration = match period {
+        "week" => 7 * 24 * 60 * 60,      // 1 week in seconds
+        "2weeks" => 14 * 24 * 60 * 60,   // 2 weeks in seconds
+        "month" => 30 * 24 * 60 * 60,    // 1 month in seconds
+        "2months" => 60 * 24 * 60 * 60,  // 2 months in seconds
+        "3months" => 90 * 24 * 60 * 60,  // 3 months in seconds
+        "6months" => 180 * 24 * 60 * 60, // 6 months in seconds
+        "year" => 365 * 24 * 60 * 60,    // 1 year in seconds
+        _ => 999999999999999,            // Invalid period
+    };
+
+    if let Ok(duration) = now.duration_since(UNIX_EPOCH) {
+        if duration.as_secs() > time + period_duration {
+            true
+        } else {
+            false
+        }
+    } else {
+        false
+    }
+}
+
+// Expands extension category names such as "documents:special" into the extensions they contain
+fn get_allowed_extensions(extensions: &[String]) -> Vec<String> {
+    let mut allowed_extensions = extensions.to_vec();
+    for extension in allowed_extensions.clone() {
+        if extension == "documents:special" {
+            allowed_extensions.extend(vec![
+                String::from("doc"),
+                String::from("docx"),
+                String::from("pdf"),
+                String::from("txt"),
+                String::from("odt"),
+                String::from("rtf"),
+                String::from("md"),
+                String::from("epub"),
+                String::from("pptx"),
+                String::from("xls"),
+                String::from("xlsx"),
+            ]);
+        }
+        if extension == "videos:special" {
+            allowed_extensions.extend(vec![
+                String::from("mp4"),
+                String::from("mkv"),
+                String::from("avi"),
+                String::from("mov"),
+                String::from("wmv"),
+                String::from("flv"),
+                String::from("webm"),
+                String::from("mpeg"),
+            ]);
+        }
+        if extension == "pictures:special" {
+            allowed_extensions.extend(vec![
+                String::from("jpg"),
+                String::from("jpeg"),
+                String::from("png"),
+                String::from("gif"),
+                String::from("bmp"),
+                String::from("tiff"),
+                String::from("webp"),
+                String::from("svg"),
+            ]);
+        }
+        if extension == "music:special" {
+            allowed_extensions.extend(vec![
+                String::from("mp3"),
+                String::from("wav"),
+                String::from("flac"),
+                String::from("aac"),
+                String::from("ogg"),
+                String::from("m4a"),
+                String::from("wma"),
+            ]);
+        }
+        if extension == "archives:special" {
+            allowed_extensions.extend(vec![
+                String::from("zip"),
+                String::from("rar"),
+                String::from("tar"),
+                String::from("gz"),
+                String::from("7z"),
+                String::from("bz2"),
+                String::from("xz"),
+            ]);
+        }
+    }
+    allowed_extensions
+}
+
+// In glob patterns '*' does not match '/', while '**' matches any number of folders
+// Builds the glob set of a pattern filter. '*' does not match '/' here, so patterns without '/' can be
+// matched against each file or folder name on their own. Restore patterns differ: they match whole
+// original paths, with '*' also matching '/'.
+fn build_filter_glob_set(patterns: &[String]) -> Result<GlobSet, String> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()
+            .map_err(|e| format!("Invalid pattern '{}': {}", pattern, e))?;
+        builder.add(glob);
+    }
+    builder.build().map_err(|e| format!("Invalid patterns: {}", e))
+}
+
+// Patterns containing '/' match the whole relative path. Other patterns match any single
+// file or folder name in it, so "node_modules" matches every file inside a node_modules folder.
+fn matches_glob_set(glob_set: &GlobSet, relative_path: &str) -> bool {
+    glob_set.is_match(relative_path)
+        || relative_path
+            .split('/')
+            .any(|component| glob_set.is_match(component))
+}
+
+fn get_filter_patterns(filter: &FileFilter) -> Vec<String> {
+    filter
+        .traits
+        .patterns
+        .clone()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|pattern| pattern.trim().to_string())
+        .filter(|pattern| !pattern.is_empty())
+        .collect()
+}
+
+// Prepares a filter for matching. Filters that are unknown or have invalid patterns give None
+// and are skipped, rather than risking files being left out of the backup.
+pub fn compile_filter(filter: &FileFilter) -> Option<CompiledFilter> {
+    match filter.filter_type.as_str() {
+        "extension" => Some(CompiledFilter::Extension(get_allowed_extensions(
+            &filter.traits.extensions.clone().unwrap_or_default(),
+        ))),
+        "size" => {
+            // The legacy size trait is a minimum in whole megabytes
+            let legacy_min = match filter.traits.size {
+                Some(size) => match size.checked_mul(1000 * 1000) {
+                    Some(size) => Some(size),
+                    None => {
+                        println!("Skipping size filter: Size '{} MB' is too large", size);
+                        return None;
+                    }
+                },
+                None => None,
+            };
+            match (
+                parse_optional_size(&filter.traits.min_size),
+                parse_optional_size(&filter.traits.max_size),
+            ) {
+                (Ok(min_size), Ok(max_size)) => {
+                    let min_size = min_size.or(legacy_min);
+                    if min_size.is_none() && max_size.is_none() {
+                        return None;
+                    }
+                    Some(CompiledFilter::Size(min_size, max_size))
+                }
+                (Err(e), _) | (_, Err(e)) => {
+                    println!("Skipping size filter: {}", e);
+                    None
+                }
+            }
+        }
+        "last-used" | "modified" | "created" => {
+            let (earliest, latest) = match get_time_range(filter) {
+                Ok(range) => range,
+                Err(e) => {
+                    println!("Skipping {} filter: {}", filter.filter_type, e);
+                    return None;
+                }
+            };
+            if earliest.is_some() || latest.is_some() {
+                return Some(CompiledFilter::Time(filter.filter_type.clone(), earliest, latest));
+            }
+            // Last used filters from before durations were supported use a fixed period
+            if filter.filter_type == "last-used" {
+                return Some(CompiledFilter::LastUsed(filter.traits.period.clone()?));
+            }
+            None
+        }
+        "include-glob" | "exclude-glob" => {
+            let patterns = get_filter_patterns(filter);
+            if patterns.is_empty() {
+                return None;
+            }
+            match build_filter_glob_set(&patterns) {
+                Ok(glob_set) => Some(CompiledFilter::Glob(
+                    glob_set,
+                    filter.filter_type == "include-glob",
+                )),
+                Err(e) => {
+                    println!("Skipping {} filter: {}", filter.filter_type, e);
+                    None
+                }
+            }
+        }
+        "regex" => {
+            let patterns = get_filter_patterns(filter);
+            if patterns.is_empty() {
+                return None;
+            }
+            match RegexSet::new(&patterns) {
+                Ok(regex_set) => Some(CompiledFilter::Regex(regex_set)),
+                Err(e) => {
+                    println!("Skipping regex filter: {}", e);
+                    None
+                }
+            }
+        }
+        _ => {
+            println!("Unknown filter type: {}", filter.filter_type);
+            None
+        }
+    }
+}
+
+impl CompiledFilter {
+    // Checks whether a file passes the filter. Glob and regex filters match
+    // the path of the file relative to its input folder.
+    pub fn matches(&self, file: &str, input_dirs: &[String]) -> bool {
+        match self {
+            CompiledFilter::Extension(allowed_extensions) => {
+                let file_extension = file.split('.').last().unwrap_or("");
+                allowed_extensions.contains(&file_extension.to_lowercase())
+            }
+            CompiledFilter::Size(min_size, max_size) => match fs::metadata(file) {
+                Ok(metadata) => {
+                    min_size.map_or(true, |min_size| metadata.len() >= min_size)
+                        && max_size.map_or(true, |max_size| metadata.len() <= max_size)
+                }
+                Err(_) => {
+                    println!("Could not get size for file: {}", file);
+                    false
+                } // If we can't get the size, exclude the file
+            },
+            CompiledFilter::LastUsed(period) => match get_last_access_time(file) {
+                Ok(last_accessed) => check_older_than(last_accessed, period.as_str()),
+                Err(_) => {
+                    println!("Could not get last accessed time for file: {}", file);
+                    false
+                } // If we can't get the last accessed time, exclude the file
+            },
+            CompiledFilter::Time(time_type, earliest, latest) => match get_file_time(file, time_type) {
+                Ok(file_time) => {
+                    earliest.map_or(true, |earliest| file_time >= earliest)
+                        && latest.map_or(true, |latest| file_time < latest)
+                }
+                // Many Linux and network file systems do not record when files were created,
+                // so files there are kept rather than every file being left out
+                Err(e) if e.kind() == std::io::ErrorKind::Unsupported => {
+                    println!("No {} time is recorded for file, keeping it: {}", time_type, file);
+                    true
+                }
+                Err(_) => {
+                    println!("Could not get {} time for file: {}", time_type, file);
+                    false
+                } // If we can't get the time, exclude the file
+            },
+            CompiledFilter::Glob(glob_set, include) => {
+                matches_glob_set(glob_set, &get_relative_path(file, input_dirs)) == *include
+            }
+            CompiledFilter::Regex(regex_set) => {
+                regex_set.is_match(&get_relative_path(file, input_dirs))
+            }
+        }
+    }
+}
+
+// Prepares a filter expression for matching. Groups left without any usable filters give None
+// so they do not affect which files are kept.
+pub fn compile_expression(expression: &FilterExpression) -> Option<CompiledExpression> {
+    let compile_children = || -> Vec<CompiledExpression> {
+        expression
+            .children
+            .iter()
+            .flatten()
+            .filter_map(compile_expression)
+            .collect()
+    };
+
+    match expression.expression_type.as_str() {
+        "all" => Some(compile_children()).filter(|children| !children.is_empty()).map(CompiledExpression::All),
+        "any" => Some(compile_children()).filter(|children| !children.is_empty()).map(CompiledExpression::Any),
+        // A not group with several children negates all of them together
+        "not" => Some(compile_children())
+            .filter(|children| !children.is_empty())
+            .map(|children| CompiledExpression::Not(Box::new(CompiledExpression::All(children)))),
+        "filter" => compile_filter(expression.filter.as_ref()?).map(CompiledExpression::Filter),
+        _ => {
+            println!("Unknown filter expression type: {}", expression.expression_type);
+            None
+        }
+    }
+}
+
+impl CompiledExpression {
+    pub fn matches(&self, file: &str, input_dirs: &[String]) -> bool {
+        match self {
+            CompiledExpression::All(children) => {
+                children.iter().all(|child| child.matches(file, input_dirs))
+            }
+            CompiledExpression::Any(children) => {
+                children.iter().any(|child| child.matches(file, input_dirs))
+            }
+            CompiledExpression::Not(child) => !child.matches(file, input_dirs),
+            CompiledExpression::Filter(filter) => filter.matches(file, input_dirs),
+        }
+    }
+}
+
+// Removes the files that do not pass a filter
+pub fn apply_file_filter(files: &mut Vec<String>, filter: &FileFilter, input_dirs: &[String]) {
+    if let Some(compiled) = compile_filter(filter) {
+        files.retain(|file| compiled.matches(file, input_dirs));
+    }
+}
+
+// Removes the files that do not pass a job's filter expression, if it has one
+pub fn apply_filter_expression(files: &mut Vec<String>, job_info: &JobInfo, input_dirs: &[String]) {
+    if let Some(compiled) = job_info
+        .filter_expression
+        .as_ref()
+        .and_then(compile_expression)
+    {
+        files.retain(|file| compiled.matches(file, input_dirs));
+    }
+}
+
+// Gets the names of the ignore files a job honors while indexing, in order of increasing priority
+pub fn get_ignore_file_names(job_info: &JobInfo) -> Vec<String> {
+    let mut ignore_file_names: Vec<String> = Vec::new();
+    if job_info.respect_gitignore.unwrap_or(false) {
+        ignore_file_names.push(String::from(".gitignore"));
+    }
+    ignore_file_names.push(String::from(".archwayignore"));
+    ignore_file_names
+}
+
+// Loads the ignore files in a folder, if it has any. Their patterns are relative to the folder.
+pub fn load_ignore_files(dir: &Path, ignore_file_names: &[String]) -> Option<Gitignore> {
+    let mut builder = GitignoreBuilder::new(dir);
+    let mut found = false;
+    for ignore_file_name in ignore_file_names {
+        let ignore_file = dir.join(ignore_file_name);
+        if !ignore_file.is_file() {
+            continue;
+        }
+        found = true;
+        if let Some(e) = builder.add(&ignore_file) {
+            println!("Problem reading ignore file {}: {}", ignore_file.display(), e);
+        }
+    }
+    if !found {
+        return None;
+    }
+
+    match builder.build() {
+        Ok(gitignore) => Some(gitignore),
+        Err(e) => {
+            println!("Failed to load ignore files in {}: {}", dir.display(), e);
+            None
+        }
+    }
+}
+
+// Checks a path against the ignore files of the folders above it, innermost first.
+// As with git, a rule in a deeper ignore file overrides the folders above it,
+// and a negated pattern ("!keep.log") includes a path that would otherwise be ignored.
+pub fn is_ignored(ignore_stack: &[Gitignore], path: &Path, is_dir: bool) -> bool {
+    for gitignore in ignore_stack.iter().rev() {
+        match gitignore.matched(path, is_dir) {
+            Match::Ignore(_) => return true,
+            Match::Whitelist(_) => return false,
+            Match::None => {}
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("12 b"), Ok(12));
+        assert_eq!(parse_size("500 MB"), Ok(500 * 1000 * 1000));
+        assert_eq!(parse_size("2KiB"), Ok(2048));
+        assert_eq!(parse_size(" 1.5 GiB "), Ok(1536 * 1024 * 1024));
+        assert_eq!(parse_size("1 tb"), Ok(1000 * 1000 * 1000 * 1000));
+    }
+
+    #[test]
+    fn rejects_invalid_sizes() {
+        assert!(parse_size("").is_err());
+        assert!(parse_size("MB").is_err());
+        assert!(parse_size("-5 MB").is_err());
+        assert!(parse_size("1.2.3 MB").is_err());
+        assert!(parse_size("5 XB").is_err());
+    }
+
+    #[test]
+    fn rejects_sizes_that_overflow() {
+        assert!(parse_size("99999999999999999999").is_err());
+        assert!(parse_size("99999999 TB").is_err());
+        let filter: FileFilter = serde_json::from_str(&format!(
+            r#"{{"filter_type": "size", "traits": {{"size": {}}}}}"#,
+            u64::MAX
+        ))
+        .unwrap();
+        assert!(compile_filter(&filter).is_none());
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("45s"), Ok(45));
+        assert_eq!(parse_duration("18h"), Ok(18 * 60 * 60));
+        assert_eq!(parse_duration("1w2d"), Ok(9 * 24 * 60 * 60));
+        assert_eq!(parse_duration(" 1Y 30m "), Ok(365 * 24 * 60 * 60 + 30 * 60));
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("45").is_err());
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("3x").is_err());
+        assert!(parse_duration("1d2").is_err());
+    }
+
+    #[test]
+    fn rejects_durations_that_overflow() {
+        assert!(parse_duration("99999999999999999999s").is_err());
+        assert!(parse_duration("999999999999999999y").is_err());
+        assert!(parse_duration(&format!("{}s1s", u64::MAX)).is_err());
+    }
+
+    #[test]
+    fn parses_dates_and_times() {
+        let day = parse_date("2024-06-01").unwrap();
+        assert_eq!(parse_date("2024-06-01 18:30").unwrap(), day + 18 * 60 * 60 + 30 * 60);
+        assert_eq!(parse_date("2024-06-01T18:30:15").unwrap(), day + 18 * 60 * 60 + 30 * 60 + 15);
+        assert_eq!(parse_date("2024-06-02").unwrap(), day + 24 * 60 * 60);
+    }
+
+    #[test]
+    fn rejects_invalid_dates() {
+        assert!(parse_date("yesterday").is_err());
+        assert!(parse_date("2024-06").is_err());
+        assert!(parse_date("2024-13-01").is_err());
+        assert!(parse_date("2023-02-29").is_err());
+        assert!(parse_date("2024-06-01 25:00").is_err());
+        assert!(parse_date("2024-06-01 18").is_err());
+    }
+}
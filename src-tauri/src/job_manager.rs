@@ -1,14 +1,18 @@
 use crate::log_manager::job_log;
 use crate::storage_manager::set_job_health_by_uuid;
-use crate::structs::JobStatus;
-use crate::{drive_manager, settings_manager, storage_manager};
+use crate::structs::{DryRunReport, FileMetadata, JobInfo, JobStatus, ManifestEntry, ResumableRun, RunManifest};
+use crate::encryption_manager::EncryptionKey;
+use crate::link_manager::{EntryKind, FileWalk};
+use crate::{archive_manager, copy_manager, dedup_manager, drive_manager, encryption_manager, filter_manager, journal_manager, link_manager, manifest_manager, metadata_manager, scrub_manager, settings_manager, storage_manager};
+use ignore::gitignore::Gitignore;
 use once_cell::sync::{Lazy, OnceCell};
 use sha2::{Digest, Sha256};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::io;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Mutex;
 use std::time::{SystemTime, UNIX_EPOCH};
 use tauri::AppHandle;
@@ -18,6 +22,8 @@ use crate::recovery_manager::get_recovery_progress;
 static APP_HANDLE: OnceCell<Mutex<AppHandle>> = OnceCell::new();
 static JOB_STATUSES: Lazy<Mutex<Vec<JobStatus>>> = Lazy::new(|| Mutex::new(Vec::new()));
 static JOB_UPDATES: OnceCell<Mutex<HashMap<String, String>>> = OnceCell::new();
+// Held while a job or a scrub checks that the other is not running and registers itself, so both cannot start at once
+pub static START_LOCK: Mutex<()> = Mutex::new(());
 
 /*
 Job Steps:
@@ -63,25 +69,347 @@ pub fn get_app_handle() -> AppHandle {
 }
 
 pub fn start_job(uuid: String) -> bool {
+    launch_job(uuid, None)
+}
+
+// Resumes a job's interrupted run from its journal instead of starting over
+pub fn resume_job(uuid: String) -> bool {
+    match journal_manager::get_interrupted_run(&uuid) {
+        Some(run_dir) => launch_job(uuid, Some(run_dir)),
+        None => {
+            println!("No interrupted run found for job with UUID {}.", uuid);
+            false
+        }
+    }
+}
+
+// Gets a summary of a job's interrupted run, if there is one to resume
+pub fn get_resumable_run(uuid: &str) -> Option<ResumableRun> {
+    let run_dir = journal_manager::get_interrupted_run(uuid)?;
+    let progress = journal_manager::read_journal(&run_dir)?;
+
+    Some(ResumableRun {
+        output_dir: progress.journal.output_dir,
+        created: progress.journal.created,
+        total_files: progress.journal.files.len() as u32,
+        copied_files: progress.copied.len() as u32,
+        verified_files: progress.verified.len() as u32,
+    })
+}
+
+// Works out which folder a run would write to, the folder of the previous run and
+// the folders that would be removed, without renaming or deleting anything
+fn predict_run_dirs(job_info: &JobInfo) -> (PathBuf, Option<PathBuf>, Vec<String>) {
+    let mut folders_to_delete: Vec<String> = Vec::new();
+
+    if job_info.storage_layout.clone().unwrap_or_default() == "dedup" {
+        let store_dir = dedup_manager::get_store_dir(&job_info.output_dir, &job_info.uuid);
+        let mut completed: Vec<PathBuf> = Vec::new();
+        for snapshot_dir in dedup_manager::get_snapshot_dirs(&store_dir) {
+            if manifest_manager::get_manifest_path(&snapshot_dir).exists() {
+                completed.push(snapshot_dir);
+            } else {
+                folders_to_delete.push(snapshot_dir.to_string_lossy().to_string());
+            }
+        }
+        let previous_run_dir = completed.last().cloned();
+
+        // The new snapshot counts towards the retained copies
+        let keep = job_info.copies.max(1) as usize;
+        if job_info.file_behavior == "copy" && completed.len() + 1 > keep {
+            for snapshot_dir in &completed[..completed.len() + 1 - keep] {
+                folders_to_delete.push(snapshot_dir.to_string_lossy().to_string());
+            }
+        }
+        return (store_dir, previous_run_dir, folders_to_delete);
+    }
+
+    let base_dir = PathBuf::from(&job_info.output_dir).join(format!("archway-{}", job_info.uuid));
+    let copies = job_info.copies;
+
+    if job_info.file_behavior == "copy" && copies > 1 {
+        let numbered_dir =
+            |number: u32| base_dir.with_file_name(format!("archway-{}-{}", job_info.uuid, number));
+
+        let mut run_dir = numbered_dir(copies);
+        let mut checked_dir = base_dir.clone();
+        let mut folder_num = 1;
+        while checked_dir.exists() && folder_num <= copies {
+            run_dir = numbered_dir(folder_num);
+            folder_num += 1;
+            checked_dir = run_dir.clone();
+        }
+
+        if folder_num > copies {
+            // All copies exist, so they are rotated and the oldest is deleted
+            let oldest_dir = numbered_dir(1);
+            folders_to_delete.push(oldest_dir.to_string_lossy().to_string());
+            let newest_dir = numbered_dir(copies);
+            return (newest_dir.clone(), Some(newest_dir), folders_to_delete);
+        }
+
+        let previous_run_dir = manifest_manager::get_previous_run_dir(&run_dir, &job_info.uuid);
+        return (run_dir, previous_run_dir, folders_to_delete);
+    }
+
+    let previous_run_dir = manifest_manager::get_previous_run_dir(&base_dir, &job_info.uuid)
+        .filter(|dir| dir.exists());
+    (base_dir, previous_run_dir, folders_to_delete)
+}
+
+// Gets the total size in bytes of all files in a folder
+fn get_dir_size(path: &str) -> u64 {
+    get_all_files(path)
+        .iter()
+        .map(|file| get_file_size(file).unwrap_or(0))
+        .sum()
+}
+
+fn format_megabytes(bytes: u64) -> String {
+    format!("{:.1} MB", bytes as f64 / 1000.0 / 1000.0)
+}
+
+// Checks that a run will fit on the output drive before anything is written.
+// Space freed by deleting the oldest rotated copy is counted as available, and files
+// that can be reused from the previous run or are already stored only need space once.
+// Rotated copies need the full size of every file, except unchanged files hard-linked by incremental jobs.
+fn check_free_space(job_info: &JobInfo, drive: &str, files: &[String]) -> Result<(), String> {
+    if drive_manager::is_fallback_drive(&job_info.output_dir, drive) {
+        return Err(String::from("The output drive is not connected."));
+    }
+    let available = match drive_manager::get_available_space(drive) {
+        Some(available) => available,
+        None => {
+            println!("Could not get available space for drive: {}", drive);
+            return Ok(());
+        }
+    };
+
+    let (run_dir, previous_run_dir, folders_to_delete) = predict_run_dirs(job_info);
+    let dedup = job_info.storage_layout.clone().unwrap_or_default() == "dedup";
+    let incremental = job_info.incremental.clone().unwrap_or("off".to_string());
+    let reuses_previous = dedup || (job_info.file_behavior == "copy" && incremental != "off");
+
+    let mut previous_entries: HashMap<String, ManifestEntry> = HashMap::new();
+    if let Some(previous_manifest) = previous_run_dir
+        .as_ref()
+        .and_then(|dir| manifest_manager::read_manifest(dir))
+    {
+        for entry in previous_manifest.files {
+            previous_entries.insert(entry.source.clone(), entry);
+        }
+    }
+    // Only a job keeping a single copy writes over its previous run. Rotated jobs report the newest copy as both
+    // the run folder and the previous run, but it is renamed and the run is written to a new folder.
+    let keeps_single_copy = job_info.file_behavior != "copy" || job_info.copies <= 1;
+    let overwrites_previous = !dedup && keeps_single_copy && previous_run_dir.as_ref() == Some(&run_dir);
+
+    let mut required: u64 = 0;
+    for file in files {
+        let (size, modified) = match manifest_manager::get_file_fingerprint(file) {
+            Ok(fingerprint) => fingerprint,
+            Err(_) => continue,
+        };
+        match previous_entries.get(file) {
+            // Files copied over their previous output only need the extra space
+            Some(entry) if overwrites_previous => required += size.saturating_sub(entry.size),
+            // Unchanged files are linked from the previous run or already stored as a blob
+            Some(entry) if reuses_previous && entry.size == size && entry.modified == modified => {}
+            _ => required += size,
+        }
+    }
+
+    // Blobs shared with retained snapshots are not freed, so only rotated folders count
+    let reclaimed: u64 = if dedup {
+        0
+    } else {
+        folders_to_delete.iter().map(|folder| get_dir_size(folder)).sum()
+    };
+
+    if required <= available.saturating_add(reclaimed) {
+        return Ok(());
+    }
+
+    let mut message = format!(
+        "Not enough free space on the output drive. The backup needs {} but only {} is free",
+        format_megabytes(required),
+        format_megabytes(available)
+    );
+    if reclaimed > 0 {
+        message += &format!(
+            " ({} more once the oldest copy is deleted)",
+            format_megabytes(reclaimed)
+        );
+    }
+    message += ".";
+
+    // Suggest keeping fewer copies when deleting more of the rotated copies would make the backup fit
+    let copies = job_info.copies;
+    if !dedup && job_info.file_behavior == "copy" && copies > 1 && !folders_to_delete.is_empty() {
+        let mut freed = available.saturating_add(reclaimed);
+        for extra_deleted in 1..copies {
+            let folder = PathBuf::from(&job_info.output_dir)
+                .join(format!("archway-{}-{}", job_info.uuid, extra_deleted + 1));
+            freed = freed.saturating_add(get_dir_size(&folder.to_string_lossy()));
+            if required <= freed {
+                message += &format!(
+                    " Keeping {} copies instead of {} would free enough space.",
+                    copies - extra_deleted,
+                    copies
+                );
+                return Err(message);
+            }
+        }
+    }
+
+    message += &format!(
+        " Free up at least {} on the drive or use a larger drive.",
+        format_megabytes(required - available.saturating_add(reclaimed))
+    );
+    Err(message)
+}
+
+// Runs the indexing, filters and output checks of a job without writing anything,
+// reporting what the job would copy and delete
+pub fn dry_run_job(uuid: &str) -> DryRunReport {
+    let job_info = storage_manager::get_job_by_uuid(uuid);
+    let mut problems: Vec<String> = Vec::new();
+
+    // Stage one: indexing and filters
+    let input_folders = get_input_folders(&job_info);
+    let mut walk = FileWalk::new(&link_manager::get_symlink_policy(&job_info));
+    let mut files = get_input_files(&input_folders, &filter_manager::get_ignore_file_names(&job_info), &mut walk);
+    for filter in job_info.file_filters.iter() {
+        filter_manager::apply_file_filter(&mut files, filter, &input_folders);
+    }
+    filter_manager::apply_filter_expression(&mut files, &job_info, &input_folders);
+    if let Err(message) = check_total_size(&job_info, &files) {
+        problems.push(message);
+    }
+
+    // Stage two: drive and output folder checks
+    let drive = drive_manager::get_root_drive(&job_info.output_dir);
+    match drive.as_ref() {
+        None => problems.push(format!(
+            "Could not determine the drive containing the output directory: '{}'.",
+            job_info.output_dir
+        )),
+        Some(drive) if !std::path::Path::new(drive).exists() => {
+            problems.push(format!("Drive does not exist: '{}'.", drive))
+        }
+        Some(drive) if drive_manager::is_fallback_drive(&job_info.output_dir, drive) => problems.push(format!(
+            "The output directory does not exist and would be created on the system drive '{}'. Is the output drive connected?",
+            drive
+        )),
+        Some(drive) => {
+            if job_info.output_device != "special:any" {
+                match drive_manager::read_drive_uuid(drive) {
+                    Some(drive_uuid) if drive_uuid == job_info.output_device => {}
+                    Some(drive_uuid) => problems.push(format!(
+                        "Drive UUID does not match job output device: {} != {}.",
+                        drive_uuid, job_info.output_device
+                    )),
+                    None => problems.push(format!(
+                        "Drive '{}' has no Archway drive UUID, so it cannot be the job output device.",
+                        drive
+                    )),
+                }
+            }
+        }
+    }
+
+    if !std::path::Path::new(&job_info.output_dir).exists() && !job_info.new_folder {
+        problems.push(format!(
+            "Output directory does not exist: '{}'.",
+            job_info.output_dir
+        ));
+    }
+
+    // The free space of the system drive says nothing about the missing output drive
+    let drive = drive.filter(|drive| !drive_manager::is_fallback_drive(&job_info.output_dir, drive));
+
+    let (run_dir, previous_run_dir, folders_to_delete) = predict_run_dirs(&job_info);
+
+    // Files unchanged since the previous run are skipped by incremental copy jobs
+    let incremental = job_info.incremental.clone().unwrap_or("off".to_string());
+    let mut previous_entries: HashMap<String, ManifestEntry> = HashMap::new();
+    if job_info.file_behavior == "copy" && incremental != "off" {
+        if let Some(previous_manifest) = previous_run_dir
+            .as_ref()
+            .and_then(|dir| manifest_manager::read_manifest(dir))
+        {
+            for entry in previous_manifest.files {
+                previous_entries.insert(entry.source.clone(), entry);
+            }
+        }
+    }
+
+    let mut files_to_copy: Vec<String> = Vec::new();
+    let mut files_unchanged: Vec<String> = Vec::new();
+    let mut total_bytes: u64 = 0;
+    let all_files = files.clone();
+    for file in files {
+        if let Some(previous_entry) = previous_entries.get(&file) {
+            if is_file_unchanged(&file, previous_entry, incremental.as_str()) {
+                files_unchanged.push(file);
+                continue;
+            }
+        }
+        match get_file_size(&file) {
+            Ok(size) => total_bytes += size,
+            Err(e) => problems.push(format!("Could not read file '{}': {}", file, e)),
+        }
+        files_to_copy.push(file);
+    }
+
+    let available_bytes = drive
+        .as_ref()
+        .and_then(|drive| drive_manager::get_available_space(drive));
+    if let Some(drive) = drive.as_ref() {
+        if let Err(message) = check_free_space(&job_info, drive, &all_files) {
+            problems.push(message);
+        }
+    }
+
+    // Stage five deletes the originals of every copied file
+    let files_to_delete = if job_info.file_behavior == "move" {
+        files_to_copy.clone()
+    } else {
+        Vec::new()
+    };
+
+    DryRunReport {
+        output_dir: run_dir.to_string_lossy().to_string(),
+        files_to_copy,
+        files_unchanged,
+        total_bytes,
+        available_bytes,
+        files_to_delete,
+        folders_to_delete,
+        problems,
+    }
+}
+
+fn launch_job(uuid: String, resume_dir: Option<PathBuf>) -> bool {
     if (get_recovery_progress() >= 0.0){
         println!("Cannot start job while recovery is in progress.");
         return false;
     }
 
-    // Check if the job is already running
-    let already_running = {
-        let job_statuses = JOB_STATUSES.lock().unwrap();
-        job_statuses
-            .iter()
-            .any(|js| js.job.uuid == uuid && !js.completed)
-    };
+    let start_guard = START_LOCK.lock().unwrap();
 
     // If the job is already running, do not start it again
-    if already_running {
+    if is_job_running(&uuid) {
         println!("Job with UUID {} is already running.", uuid);
         return false;
     }
 
+    // Runs could be rotated away while the scrub is reading them
+    if scrub_manager::is_scrubbing(&uuid) {
+        println!("Cannot start job with UUID {} while its backups are being scrubbed.", uuid);
+        return false;
+    }
+
     clear_job(&uuid);
 
     let new_job = storage_manager::get_job_by_uuid(&uuid);
@@ -99,12 +427,28 @@ pub fn start_job(uuid: String) -> bool {
         success: true,
         completed: false,
         percent: 0.0,
+        skipped: 0,
+        failed_files: Vec::new(),
     };
 
     JOB_STATUSES.lock().unwrap().push(new_job_status);
+    drop(start_guard);
     set_job_update(uuid.clone(), "running".to_string());
 
-    tauri::async_runtime::spawn(job_stage_one(uuid.clone()));
+    match resume_dir {
+        Some(run_dir) => {
+            // Reuse the file index from the interrupted run
+            let files = match journal_manager::read_journal(&run_dir) {
+                Some(progress) => progress.journal.files,
+                None => Vec::new(),
+            };
+            tauri::async_runtime::spawn(job_stage_two(uuid.clone(), files, Some(run_dir)));
+        }
+        None => {
+            journal_manager::clear_interrupted_run(&uuid);
+            tauri::async_runtime::spawn(job_stage_one(uuid.clone()));
+        }
+    }
 
     get_app_handle()
         .notification()
@@ -166,6 +510,73 @@ fn update_last_action(uuid: &str, last_action: String) {
     }
 }
 
+fn get_skipped_files(uuid: &str) -> u32 {
+    let job_statuses = JOB_STATUSES.lock().unwrap();
+    job_statuses
+        .iter()
+        .find(|js| js.job.uuid == uuid)
+        .map_or(0, |js| js.skipped)
+}
+
+fn increment_skipped_files(uuid: &str) {
+    let mut job_statuses = JOB_STATUSES.lock().unwrap();
+    if let Some(job_status) = job_statuses.iter_mut().find(|js| js.job.uuid == uuid) {
+        job_status.skipped += 1;
+    }
+}
+
+fn get_failed_files(uuid: &str) -> Vec<String> {
+    let job_statuses = JOB_STATUSES.lock().unwrap();
+    job_statuses
+        .iter()
+        .find(|js| js.job.uuid == uuid)
+        .map_or(Vec::new(), |js| js.failed_files.clone())
+}
+
+// Leaves a file out of the run after it could not be copied or verified, for jobs that continue on errors
+fn skip_failed_file(uuid: &str, file: &str, message: &str, log_level: &str) {
+    println!("Skipping file {}: {}", file, message);
+    job_log(
+        uuid,
+        &format!("Skipped file {}: {}", file, message),
+        "ERROR",
+        log_level.to_string(),
+    );
+    let mut job_statuses = JOB_STATUSES.lock().unwrap();
+    if let Some(job_status) = job_statuses.iter_mut().find(|js| js.job.uuid == uuid) {
+        job_status.failed_files.push(file.to_string());
+    }
+}
+
+// Removes the entries of files left out of a run, keeping the run's lists lined up with each other
+fn remove_failed<T>(items: Vec<T>, failed_indices: &HashSet<usize>) -> Vec<T> {
+    items
+        .into_iter()
+        .enumerate()
+        .filter(|(index, _)| !failed_indices.contains(index))
+        .map(|(_, item)| item)
+        .collect()
+}
+
+// Describes the files left out of a run, for the message the run completes with
+fn describe_failed_files(failed_files: &[String]) -> String {
+    let mut listed = failed_files
+        .iter()
+        .take(10)
+        .cloned()
+        .collect::<Vec<String>>()
+        .join(", ");
+    if failed_files.len() > 10 {
+        listed.push_str(&format!(" and {} more", failed_files.len() - 10));
+    }
+    format!(
+        "Completed with {} warning{}. Skipped files: {}",
+        failed_files.len(),
+        if failed_files.len() == 1 { "" } else { "s" },
+        listed
+    )
+}
+
 pub fn clear_completed_jobs() {
     let mut job_statuses = JOB_STATUSES.lock().unwrap();
     job_statuses.retain(|js| !js.completed);
@@ -178,19 +589,33 @@ fn update_job_progress(uuid: &str, percent: f32) {
     }
 }
 
-// Gets all subfolders recursively from a given path
-fn get_all_subfolders(path: &str) -> Vec<String> {
-    let mut subfolders = Vec::new();
-    if let Ok(entries) = std::fs::read_dir(path) {
-        for entry in entries.flatten() {
-            if entry.file_type().map_or(false, |ft| ft.is_dir()) {
-                let subfolder_path = entry.path().to_string_lossy().to_string();
-                subfolders.push(subfolder_path.clone());
-                subfolders.extend(get_all_subfolders(&subfolder_path));
+// Gets the folder of a library such as "documents" for the current user
+pub fn get_library_dir(library: &str) -> Option<PathBuf> {
+    match library {
+        "documents" => dirs::document_dir(),
+        "downloads" => dirs::download_dir(),
+        "desktop" => dirs::desktop_dir(),
+        "music" => dirs::audio_dir(),
+        "pictures" => dirs::picture_dir(),
+        "videos" => dirs::video_dir(),
+        _ => None,
+    }
+}
+
+// Gets the folders of all libraries used as input by a job, keyed by library name
+fn get_job_library_dirs(job_info: &JobInfo) -> HashMap<String, String> {
+    let mut library_dirs: HashMap<String, String> = HashMap::new();
+    for input_dir in &job_info.input_dirs {
+        if input_dir.path_type == "library" {
+            if let Some(library_path) = get_library_dir(&input_dir.path) {
+                library_dirs.insert(
+                    input_dir.path.clone(),
+                    library_path.to_string_lossy().to_string(),
+                );
             }
         }
     }
-    subfolders
+    library_dirs
 }
 
 // Gets all files recursively from a given path
@@ -208,37 +633,54 @@ fn get_all_files(path: &str) -> Vec<String> {
     files
 }
 
-// Gets the last access time of a file in seconds since UNIX_EPOCH
-fn get_last_access_time(path: &str) -> std::io::Result<u64> {
-    let metadata = fs::metadata(path)?;
-    let atime = metadata.accessed()?;
-    let duration = atime.duration_since(UNIX_EPOCH).unwrap();
-    Ok(duration.as_secs())
-}
-
-// Checks if a file's last access time is older than a specified period
-fn check_older_than(time: u64, period: &str) -> bool {
-    let now = SystemTime::now();
-    let period_duration = match period {
-        "week" => 7 * 24 * 60 * 60,      // 1 week in seconds
-        "2weeks" => 14 * 24 * 60 * 60,   // 2 weeks in seconds
-        "month" => 30 * 24 * 60 * 60,    // 1 month in seconds
-        "2months" => 60 * 24 * 60 * 60,  // 2 months in seconds
-        "3months" => 90 * 24 * 60 * 60,  // 3 months in seconds
-        "6months" => 180 * 24 * 60 * 60, // 6 months in seconds
-        "year" => 365 * 24 * 60 * 60,    // 1 year in seconds
-        _ => 999999999999999,            // Invalid period
+// Gets all files recursively from a given path, skipping files excluded by ignore files
+// found along the way, such as .archwayignore, and links and special files the walk leaves out
+fn get_all_files_with_ignores(
+    path: &str,
+    ignore_file_names: &[String],
+    ignore_stack: &mut Vec<Gitignore>,
+    walk: &mut FileWalk,
+) -> Vec<String> {
+    let mut files = Vec::new();
+    let loaded = match filter_manager::load_ignore_files(Path::new(path), ignore_file_names) {
+        Some(gitignore) => {
+            ignore_stack.push(gitignore);
+            true
+        }
+        None => false,
     };
 
-    if let Ok(duration) = now.duration_since(UNIX_EPOCH) {
-        if duration.as_secs() > time + period_duration {
-            true
-        } else {
-            false
+    if let Ok(entries) = std::fs::read_dir(path) {
+        for entry in entries.flatten() {
+            let entry_kind = walk.get_entry_kind(&entry);
+            let is_file = entry_kind == EntryKind::File;
+            let is_dir = entry_kind == EntryKind::Dir;
+            if (is_file || is_dir) && filter_manager::is_ignored(ignore_stack, &entry.path(), is_dir) {
+                continue;
+            }
+
+            if is_file {
+                files.push(entry.path().to_string_lossy().to_string());
+            } else if is_dir {
+                // Followed links can lead back to a folder that is already being walked
+                if !walk.enter_dir(&entry.path()) {
+                    walk.skip(&entry.path(), "the folder was already indexed");
+                    continue;
+                }
+                files.extend(get_all_files_with_ignores(
+                    &entry.path().to_string_lossy(),
+                    ignore_file_names,
+                    ignore_stack,
+                    walk,
+                ));
+            }
         }
-    } else {
-        false
     }
+
+    if loaded {
+        ignore_stack.pop();
+    }
+    files
 }
 
 // Gets the size of a file in bytes
@@ -254,10 +696,428 @@ fn file_hash(path: &str) -> std::io::Result<Vec<u8>> {
     Ok(hasher.finalize().to_vec())
 }
 
-fn compare_files(file1: &str, file2: &str) -> std::io::Result<bool> {
-    let hash1 = file_hash(file1)?;
-    let hash2 = file_hash(file2)?;
-    Ok(hash1 == hash2)
+// A file for the copy workers to copy and verify
+struct CopyTask {
+    index: usize,
+    source: String,
+    display_path: String,
+    // Where the file is copied to, unless it goes into a deduplicated store
+    output: Option<PathBuf>,
+    // The store directory and known hash for files stored by hash
+    blob: Option<(PathBuf, Option<String>)>,
+    // The hash of the stored file from the previous run, for encrypted blobs that are already stored
+    known_stored_hash: Option<String>,
+    // The metadata of the source, applied to copies that are not in a deduplicated store
+    metadata: Option<FileMetadata>,
+    unverified_copy: bool,
+}
+
+struct CopyOutcome {
+    output: PathBuf,
+    hash: String,
+    // The hash of the file as stored, when it is encrypted
+    stored_hash: Option<String>,
+    verified: bool,
+    already_stored: bool,
+}
+
+// Hashes an output file's contents, decrypting it first if the job is encrypted
+fn hash_output(path: &Path, encryption_key: Option<&EncryptionKey>) -> io::Result<Vec<u8>> {
+    match encryption_key {
+        Some(key) => encryption_manager::hash_decrypted(path, key),
+        None => file_hash(path.to_string_lossy().as_ref()),
+    }
+}
+
+// Checks a written file against the hash of its source
+fn verify_output(
+    uuid: &str,
+    output_dir: &Path,
+    task: &CopyTask,
+    output: &Path,
+    hash: &str,
+    encryption_key: Option<&EncryptionKey>,
+    log_level: &str,
+) -> bool {
+    match hash_output(output, encryption_key) {
+        Ok(output_hash) if manifest_manager::hash_to_hex(&output_hash) == hash => {
+            journal_manager::record_verified(output_dir, task.source.as_str(), hash);
+            job_log(uuid, "Verified. ", "FILE", log_level.to_string());
+            true
+        }
+        Ok(_) => {
+            println!("File verification failed for: {}", output.display());
+            false
+        }
+        Err(e) => {
+            println!("Error verifying file {}: {}", output.display(), e);
+            false
+        }
+    }
+}
+
+// Applies the metadata of a source file to its copy, logging anything that could not be applied
+fn apply_file_metadata(uuid: &str, path: &Path, metadata: Option<&FileMetadata>, log_level: &str) {
+    if let Some(metadata) = metadata {
+        for warning in metadata_manager::apply_metadata(path, metadata) {
+            println!("{}", warning);
+            job_log(uuid, &warning, "FILE", log_level.to_string());
+        }
+    }
+}
+
+// Copies one file on a copy worker, hashing it as it is read, then verifies the written file
+fn run_copy_task(
+    uuid: &str,
+    task: &CopyTask,
+    output_dir: &Path,
+    encryption_key: Option<&EncryptionKey>,
+    log_level: &str,
+) -> Result<CopyOutcome, String> {
+    job_log(
+        uuid,
+        &format!("Copying file: {}", task.source),
+        "FILE",
+        log_level.to_string(),
+    );
+
+    if let Some((store_dir, known_hash)) = task.blob.as_ref() {
+        let hash = match known_hash {
+            Some(hash) => hash.clone(),
+            None => match file_hash(task.source.as_str()) {
+                Ok(hash) => manifest_manager::hash_to_hex(&hash),
+                Err(e) => {
+                    println!("Failed to hash file {}: {}", task.source, e);
+                    return Err(format!("Failed to read file: {}", task.source));
+                }
+            },
+        };
+
+        let blob_path = dedup_manager::get_blob_path(store_dir, &hash)?;
+        // Blobs stored before encryption was turned on or off, or under an older salt, are stored again the way this run stores files
+        let stored_differently = !encryption_manager::is_stored_with(&blob_path, encryption_key);
+        let mut stored_hash: Option<String> = None;
+        if blob_path.exists() && !stored_differently {
+            if encryption_key.is_some() {
+                stored_hash = match task.known_stored_hash.clone() {
+                    Some(known_stored_hash) => Some(known_stored_hash),
+                    None => file_hash(blob_path.to_string_lossy().as_ref())
+                        .ok()
+                        .map(|blob_hash| manifest_manager::hash_to_hex(&blob_hash)),
+                };
+            }
+            if !task.unverified_copy {
+                journal_manager::record_verified(output_dir, task.source.as_str(), &hash);
+                increment_skipped_files(uuid);
+                return Ok(CopyOutcome {
+                    output: blob_path,
+                    hash,
+                    stored_hash,
+                    verified: true,
+                    already_stored: true,
+                });
+            }
+        } else {
+            match dedup_manager::store_blob(task.source.as_str(), &blob_path, &hash, encryption_key) {
+                Ok(blob_hash) => {
+                    if encryption_key.is_some() {
+                        stored_hash = Some(manifest_manager::hash_to_hex(&blob_hash));
+                    }
+                }
+                Err(e) => {
+                    println!("Failed to store file {}: {}", task.source, e);
+                    return Err(format!("Failed to copy file: {}", task.source));
+                }
+            }
+            journal_manager::record_copied(output_dir, task.source.as_str(), &hash);
+        }
+
+        let verified = verify_output(uuid, output_dir, task, &blob_path, &hash, encryption_key, log_level);
+        return Ok(CopyOutcome {
+            output: blob_path,
+            hash,
+            stored_hash,
+            verified,
+            already_stored: false,
+        });
+    }
+
+    let output = task.output.clone().unwrap();
+    let (hash, stored_hash) = match copy_manager::copy_file_with_hash(task.source.as_str(), &output, encryption_key) {
+        Ok((hash, stored_hash)) => (
+            manifest_manager::hash_to_hex(&hash),
+            encryption_key.map(|_| manifest_manager::hash_to_hex(&stored_hash)),
+        ),
+        Err(e) => {
+            println!("Failed to copy file {}: {}", task.source, e);
+            return Err(format!("Failed to copy file: {}", task.source));
+        }
+    };
+    journal_manager::record_copied(output_dir, task.source.as_str(), &hash);
+
+    let verified = verify_output(uuid, output_dir, task, &output, &hash, encryption_key, log_level);
+    // Times are applied after verifying, since reading the copy back changes its access time
+    apply_file_metadata(uuid, &output, task.metadata.as_ref(), log_level);
+    Ok(CopyOutcome {
+        output,
+        hash,
+        stored_hash,
+        verified,
+        already_stored: false,
+    })
+}
+
+// Gets the path a file is backed up at inside the run folder.
+// The input directory is removed from the file path so the directory structure is preserved.
+fn get_relative_output_path(file: &str, input_dirs: &[String]) -> String {
+    let file_path = PathBuf::from(file);
+    let mut file_path_str = file_path.to_string_lossy().to_string();
+
+    let longest_matching_dir = input_dirs
+        .iter()
+        .filter(|input_dir| file_path_str.starts_with(*input_dir))
+        .max_by_key(|input_dir| input_dir.len());
+    if let Some(longest_dir) = longest_matching_dir {
+        // Keep the last child directory in the path
+
+        let mut parts: Vec<&str> = Vec::new();
+
+        if longest_dir.contains('\\') {
+            parts = longest_dir.split('\\').collect();
+        } else if longest_dir.contains('/') {
+            parts = longest_dir.split('/').collect();
+        }
+
+        // Get the last part of the path
+        let last_part = parts.pop().unwrap();
+
+        // Remove the longest matching input directory from the file path
+        file_path_str = last_part.to_string() + &file_path_str.replace(longest_dir, "");
+    }
+
+    file_path_str
+}
+
+// Gets the manifest entry of a symbolic link backed up as a link, which records its target instead of contents
+fn get_link_manifest_entry(file: &str, output: String, link_target: String) -> ManifestEntry {
+    ManifestEntry {
+        source: file.to_string(),
+        output,
+        size: 0,
+        modified: 0,
+        hash: None,
+        stored_hash: None,
+        metadata: None,
+        link_target: Some(link_target),
+    }
+}
+
+// Writes the files into archive volumes in the run folder instead of handing them to the copy workers.
+// Archives are written one file at a time and verified by reading the volumes back in stage four.
+// Returns None if the job was stopped.
+fn write_archive_run(
+    uuid: &str,
+    files: &[String],
+    input_dirs: &[String],
+    output_dir: &Path,
+    job_info: &JobInfo,
+    output_format: &str,
+    log_level: &str,
+) -> Result<Option<(Vec<String>, Vec<Vec<String>>, Vec<ManifestEntry>)>, String> {
+    // Volumes cannot be appended to, so interrupted runs and runs reusing their folder are written again from the start
+    archive_manager::remove_volumes(output_dir);
+    let volume_size = job_info.archive_volume_size.unwrap_or(0) * 1024 * 1024;
+    let mut writer = archive_manager::VolumeWriter::new(output_dir, output_format, volume_size);
+    job_log(
+        uuid,
+        &format!("Writing {} files to {} archives", files.len(), output_format),
+        "STEP",
+        log_level.to_string(),
+    );
+
+    let total_files = files.len() as u32;
+    let symlink_policy = link_manager::get_symlink_policy(job_info);
+    let error_policy = copy_manager::get_error_policy(job_info);
+    let mut output_paths: Vec<String> = Vec::new();
+    let mut recovery_paths: Vec<Vec<String>> = Vec::new();
+    let mut manifest_entries: Vec<ManifestEntry> = Vec::new();
+    for (index, file) in files.iter().enumerate() {
+        if handle_pause_stop(uuid.to_string()) {
+            return Ok(None);
+        }
+        let processed = index as u32 + 1;
+
+        let entry_name = get_relative_output_path(file, input_dirs)
+            .replace('\\', "/")
+            .trim_start_matches('/')
+            .to_string();
+        job_log(
+            uuid,
+            &format!("Archiving file: {}", file),
+            "FILE",
+            log_level.to_string(),
+        );
+
+        // Links kept as links are only recorded in the manifest, which recovery recreates them from
+        if symlink_policy == "link" {
+            if let Some(link_target) = link_manager::read_link_target(file) {
+                let output = output_dir.join(&entry_name).to_string_lossy().to_string();
+                output_paths.push(output.clone());
+                recovery_paths.push(vec![output, file.to_string()]);
+                manifest_entries.push(get_link_manifest_entry(file, entry_name.clone(), link_target));
+                update_job_progress(uuid, processed as f32 / total_files as f32);
+                continue;
+            }
+        }
+
+        let (file_size, file_modified) =
+            manifest_manager::get_file_fingerprint(file).unwrap_or((0, 0));
+        // The metadata is read before archiving, since reading the file changes its access time
+        let file_metadata = metadata_manager::read_metadata(file, job_info);
+        // Only opening the file is retried, since a failure while writing leaves the volume unusable
+        let mut stopped = false;
+        let source = copy_manager::retry(
+            &error_policy,
+            || {
+                stopped = handle_pause_stop(uuid.to_string());
+                stopped
+            },
+            || fs::File::open(file).map_err(|e| format!("Failed to open file {}: {}", file, e)),
+        );
+        if stopped {
+            return Ok(None);
+        }
+        let source = match source {
+            Ok(source) => source,
+            Err(message) if error_policy.continue_on_error => {
+                skip_failed_file(uuid, file, &message, log_level);
+                journal_manager::record_failed(output_dir, file);
+                update_job_progress(uuid, processed as f32 / total_files as f32);
+                continue;
+            }
+            Err(message) => {
+                println!("{}", message);
+                return Err(format!("Failed to copy file: {}", file));
+            }
+        };
+        let (output, hash) = writer.add_file(source, &entry_name).map_err(|e| {
+            println!("Failed to archive file {}: {}", file, e);
+            format!("Failed to copy file: {}", file)
+        })?;
+        let hash = manifest_manager::hash_to_hex(&hash);
+        journal_manager::record_copied(output_dir, file, &hash);
+
+        output_paths.push(output.to_string_lossy().to_string());
+        recovery_paths.push(vec![output.to_string_lossy().to_string(), file.to_string()]);
+        manifest_entries.push(ManifestEntry {
+            source: file.to_string(),
+            output: output
+                .strip_prefix(output_dir)
+                .unwrap_or(&output)
+                .to_string_lossy()
+                .to_string(),
+            size: file_size,
+            modified: file_modified,
+            hash: Some(hash),
+            stored_hash: None,
+            metadata: file_metadata,
+            link_target: None,
+        });
+
+        update_job_progress(uuid, processed as f32 / total_files as f32);
+        update_last_action(
+            uuid,
+            format!("Archived file: {} ({}/{})", entry_name, processed, total_files),
+        );
+    }
+
+    writer.finish().map_err(|e| {
+        println!("Failed to finish archive: {}", e);
+        String::from("Failed to finish writing the archive.")
+    })?;
+    Ok(Some((output_paths, recovery_paths, manifest_entries)))
+}
+
+// Checks if a file is unchanged since it was recorded in a previous run's manifest
+// "metadata" compares size and modification time, "hash" compares size and SHA-256
+fn is_file_unchanged(file: &str, previous_entry: &ManifestEntry, mode: &str) -> bool {
+    let (size, modified) = match manifest_manager::get_file_fingerprint(file) {
+        Ok(fingerprint) => fingerprint,
+        Err(_) => return false,
+    };
+
+    if size != previous_entry.size {
+        return false;
+    }
+
+    match mode {
+        "metadata" => modified == previous_entry.modified,
+        "hash" => match (&previous_entry.hash, file_hash(file)) {
+            (Some(previous_hash), Ok(hash)) => *previous_hash == manifest_manager::hash_to_hex(&hash),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+// Gets every input folder of a job, converting library paths to actual directories.
+// Only the top-level folders are returned, since files are listed recursively and filter patterns
+// match paths relative to these folders. A library is only its resolved folder, not its name.
+fn get_input_folders(job_info: &JobInfo) -> Vec<String> {
+    let mut all_folders: Vec<String> = Vec::new();
+
+    for input_dir in &job_info.input_dirs {
+        if input_dir.path_type == "library" {
+            match get_library_dir(&input_dir.path) {
+                Some(library_path) => all_folders.push(library_path.to_string_lossy().to_string()),
+                None => println!("Unknown library path: {}", input_dir.path),
+            }
+        } else {
+            all_folders.push(input_dir.path.clone());
+        }
+    }
+
+    all_folders
+}
+
+// Gets all files from the given input folders. Files in overlapping input folders are listed once,
+// so a file is copied and counted once even when one input folder is inside another.
+fn get_input_files(folders: &[String], ignore_file_names: &[String], walk: &mut FileWalk) -> Vec<String> {
+    let mut all_files: Vec<String> = Vec::new();
+    let mut seen_files: HashSet<String> = HashSet::new();
+    for input_dir in folders.iter() {
+        let mut ignore_stack: Vec<Gitignore> = Vec::new();
+        walk.enter_dir(Path::new(input_dir));
+        for file in get_all_files_with_ignores(input_dir.as_str(), ignore_file_names, &mut ignore_stack, walk) {
+            if seen_files.insert(file.clone()) {
+                all_files.push(file);
+            }
+        }
+    }
+    all_files
+}
+
+// Checks that the files selected by a job's filters are within its total size cap
+fn check_total_size(job_info: &JobInfo, files: &[String]) -> Result<(), String> {
+    let max_total_size = match job_info.max_total_size.as_ref() {
+        Some(max_total_size) if !max_total_size.trim().is_empty() => max_total_size,
+        _ => return Ok(()),
+    };
+    let max_bytes = filter_manager::parse_size(max_total_size)
+        .map_err(|e| format!("Invalid total size cap: {}", e))?;
+
+    let total_bytes: u64 = files
+        .iter()
+        .map(|file| get_file_size(file).unwrap_or(0))
+        .sum();
+    if total_bytes > max_bytes {
+        return Err(format!(
+            "The selected files total {} which is more than the job's cap of {}.",
+            format_megabytes(total_bytes),
+            max_total_size
+        ));
+    }
+    Ok(())
 }
 
 // Stage one of the job: Indexing files to move
@@ -279,37 +1139,8 @@ async fn job_stage_one(uuid: String) {
         log_level.clone(),
     );
 
-    let input_dirs = storage_manager::get_job_by_uuid(&uuid).input_dirs;
-    let mut all_folders: Vec<String> = Vec::new();
-
-    // Convert library paths to actual directories
-    for input_dir in input_dirs {
-        if input_dir.path_type == "library" {
-            if input_dir.path == "documents" {
-                let documents_path = dirs::document_dir().unwrap();
-                all_folders.push(documents_path.to_string_lossy().to_string());
-            } else if input_dir.path == "downloads" {
-                let downloads_path = dirs::download_dir().unwrap();
-                all_folders.push(downloads_path.to_string_lossy().to_string());
-            } else if input_dir.path == "desktop" {
-                let desktop_path = dirs::desktop_dir().unwrap();
-                all_folders.push(desktop_path.to_string_lossy().to_string());
-            } else if input_dir.path == "music" {
-                let music_path = dirs::audio_dir().unwrap();
-                all_folders.push(music_path.to_string_lossy().to_string());
-            } else if input_dir.path == "pictures" {
-                let pictures_path = dirs::picture_dir().unwrap();
-                all_folders.push(pictures_path.to_string_lossy().to_string());
-            } else if input_dir.path == "videos" {
-                let videos_path = dirs::video_dir().unwrap();
-                all_folders.push(videos_path.to_string_lossy().to_string());
-            } else {
-                println!("Unknown library path: {}", input_dir.path);
-            }
-        }
-        all_folders.push(input_dir.path.clone());
-        all_folders.extend(get_all_subfolders(&input_dir.path));
-    }
+    let job_info = storage_manager::get_job_by_uuid(&uuid);
+    let all_folders = get_input_folders(&job_info);
 
     job_log(
         uuid.clone().as_str(),
@@ -319,10 +1150,11 @@ async fn job_stage_one(uuid: String) {
     );
     update_last_action(uuid.as_str(), String::from("Getting all files..."));
     update_job_progress(uuid.as_str(), 0.33);
-    let mut all_files: Vec<String> = Vec::new();
     // Get all files from the input directories
-    for input_dir in all_folders.iter() {
-        all_files.extend(get_all_files(input_dir.as_str()));
+    let mut walk = FileWalk::new(&link_manager::get_symlink_policy(&job_info));
+    let mut all_files = get_input_files(&all_folders, &filter_manager::get_ignore_file_names(&job_info), &mut walk);
+    for skipped in walk.skipped.iter() {
+        job_log(uuid.clone().as_str(), skipped, "FILE", log_level.clone());
     }
 
     job_log(
@@ -333,127 +1165,59 @@ async fn job_stage_one(uuid: String) {
     );
     update_last_action(uuid.as_str(), String::from("Applying filters..."));
     update_job_progress(uuid.as_str(), 0.66);
-    let filters = storage_manager::get_job_by_uuid(&uuid).file_filters;
     // Apply filters to the files
-    for filter in filters {
+    for filter in job_info.file_filters.iter() {
         job_log(
             uuid.clone().as_str(),
-            &format!("Applying filter: {}", filter.clone().filter_type),
+            &format!("Applying filter: {}", filter.filter_type),
             "FILE",
             log_level.clone(),
         );
-        // Apply extension filter
-        if filter.filter_type == "extension" {
-            let mut allowed_extensions = filter.traits.extensions.unwrap();
-            for extension in allowed_extensions.clone() {
-                if extension == "documents:special" {
-                    allowed_extensions.extend(vec![
-                        String::from("doc"),
-                        String::from("docx"),
-                        String::from("pdf"),
-                        String::from("txt"),
-                        String::from("odt"),
-                        String::from("rtf"),
-                        String::from("md"),
-                        String::from("epub"),
-                        String::from("pptx"),
-                        String::from("xls"),
-                        String::from("xlsx"),
-                    ]);
-                }
-                if extension == "videos:special" {
-                    allowed_extensions.extend(vec![
-                        String::from("mp4"),
-                        String::from("mkv"),
-                        String::from("avi"),
-                        String::from("mov"),
-                        String::from("wmv"),
-                        String::from("flv"),
-                        String::from("webm"),
-                        String::from("mpeg"),
-                    ]);
-                }
-                if extension == "pictures:special" {
-                    allowed_extensions.extend(vec![
-                        String::from("jpg"),
-                        String::from("jpeg"),
-                        String::from("png"),
-                        String::from("gif"),
-                        String::from("bmp"),
-                        String::from("tiff"),
-                        String::from("webp"),
-                        String::from("svg"),
-                    ]);
-                }
-                if extension == "music:special" {
-                    allowed_extensions.extend(vec![
-                        String::from("mp3"),
-                        String::from("wav"),
-                        String::from("flac"),
-                        String::from("aac"),
-                        String::from("ogg"),
-                        String::from("m4a"),
-                        String::from("wma"),
-                    ]);
-                }
-                if extension == "archives:special" {
-                    allowed_extensions.extend(vec![
-                        String::from("zip"),
-                        String::from("rar"),
-                        String::from("tar"),
-                        String::from("gz"),
-                        String::from("7z"),
-                        String::from("bz2"),
-                        String::from("xz"),
-                    ]);
-                }
-            }
-
-            all_files.retain(|file| {
-                let file_extension = file.split('.').last().unwrap_or("");
-                allowed_extensions.contains(&file_extension.to_lowercase())
-            });
-        }
-        // Apply size filter
-        if filter.filter_type == "size" {
-            let threshold = filter.traits.size.unwrap();
-
-            all_files.retain(|file| {
-                match get_file_size(file.as_str()) {
-                    Ok(size) => (size / 1000) / 1000 >= threshold,
-                    Err(_) => {
-                        println!("Could not get size for file: {}", file);
-                        false
-                    } // If we can't get the size, exclude the file
-                }
-            });
-        }
-        // Apply last used filter
-        if filter.filter_type == "last-used" {
-            let threshold = filter.traits.period.unwrap();
-            all_files.retain(|file| {
-                match get_last_access_time(file.as_str()) {
-                    Ok(last_accessed) => check_older_than(last_accessed, threshold.as_str()),
-                    Err(_) => {
-                        println!("Could not get last accessed time for file: {}", file);
-                        false
-                    } // If we can't get the last accessed time, exclude the file
-                }
-            });
-        }
+        filter_manager::apply_file_filter(&mut all_files, filter, &all_folders);
         if handle_pause_stop(uuid.clone()) {
             return;
         }
     }
+    if job_info.filter_expression.is_some() {
+        job_log(
+            uuid.clone().as_str(),
+            "Applying filter expression",
+            "FILE",
+            log_level.clone(),
+        );
+        filter_manager::apply_filter_expression(&mut all_files, &job_info, &all_folders);
+    }
+
+    if let Err(message) = check_total_size(&job_info, &all_files) {
+        println!("{}", message);
+        job_log(
+            uuid.clone().as_str(),
+            &format!("Job failed. {}", message),
+            "ERROR",
+            log_level.clone(),
+        );
+        update_job_status(
+            uuid.as_str(),
+            1,
+            String::from("Job failed."),
+            message,
+            false,
+            true,
+            0.0,
+        );
+        set_job_update(uuid.clone(), "not_running".to_string());
+        job_failed_notification(job_info.uuid);
+        return;
+    }
 
     println!("All folders to move: {:?}", all_folders);
     println!("All files to move: {:?}", all_files);
 
-    tauri::async_runtime::spawn(job_stage_two(uuid, all_files));
+    tauri::async_runtime::spawn(job_stage_two(uuid, all_files, None));
 }
 
 // Stage two of the job: Initializing directories
-async fn job_stage_two(uuid: String, files: Vec<String>) {
+async fn job_stage_two(uuid: String, files: Vec<String>, resume_dir: Option<PathBuf>) {
     let log_level = settings_manager::get_settings().log_level.unwrap();
     update_job_status(
         uuid.as_str(),
@@ -479,8 +1243,25 @@ async fn job_stage_two(uuid: String, files: Vec<String>) {
     let output_device = job_info.output_device.clone();
 
     let mut output_dir = job_info.output_dir.clone();
-    let drive = drive_manager::get_root_drive(output_dir.as_str()).unwrap();
-    let drive_uuid = drive_manager::get_drive_uuid(drive.as_str());
+    let drive = match drive_manager::get_root_drive(output_dir.as_str()) {
+        Some(drive) => drive,
+        None => {
+            println!("Failed to determine root drive for output directory: {}", output_dir);
+            job_log(uuid.clone().as_str(), &format!("Job failed. Could not determine the drive containing the output directory: '{}'. This error is often caused by the drive being disconnected.", output_dir), "ERROR", log_level.clone());
+            update_job_status(
+                uuid.as_str(),
+                2,
+                String::from("Job failed."),
+                String::from("Could not determine output drive."),
+                false,
+                true,
+                0.0,
+            );
+            set_job_update(uuid.clone(), "not_running".to_string());
+            job_failed_notification(job_info.uuid);
+            return;
+        }
+    };
 
     if !std::path::Path::new(&drive).exists() {
         println!("Drive does not exist: {}", drive);
@@ -499,6 +1280,27 @@ async fn job_stage_two(uuid: String, files: Vec<String>) {
         return;
     }
 
+    // An unplugged drive's output directory would otherwise be created on the system drive
+    if drive_manager::is_fallback_drive(&output_dir, &drive) {
+        println!("Output drive is not connected: {}", output_dir);
+        job_log(uuid.clone().as_str(), &format!("Job failed. The output directory '{}' does not exist and would be created on the system drive '{}'. This error is often caused by the drive being disconnected.", output_dir, drive), "ERROR", log_level.clone());
+        update_job_status(
+            uuid.as_str(),
+            2,
+            String::from("Job failed."),
+            String::from("Output drive is not connected."),
+            false,
+            true,
+            0.0,
+        );
+        set_job_update(uuid.clone(), "not_running".to_string());
+        job_failed_notification(job_info.uuid);
+        return;
+    }
+
+    // The drive UUID is only read or created once the drive is known to be the right one
+    let drive_uuid = drive_manager::get_drive_uuid(drive.as_str());
+
     // Ensure the output device matches the drive UUID
     if output_device != "special:any" {
         if drive_uuid.is_empty() {
@@ -588,6 +1390,105 @@ async fn job_stage_two(uuid: String, files: Vec<String>) {
         }
     }
 
+    // Make sure the run fits on the drive before anything is copied.
+    // Interrupted runs are resumed as is since part of the copy is already on the drive.
+    if resume_dir.is_none() {
+        if let Err(message) = check_free_space(&job_info, &drive, &files) {
+            println!("{}", message);
+            job_log(
+                uuid.clone().as_str(),
+                &format!("Job failed. {}", message),
+                "ERROR",
+                log_level.clone(),
+            );
+            update_job_status(
+                uuid.as_str(),
+                2,
+                String::from("Job failed."),
+                message,
+                false,
+                true,
+                0.0,
+            );
+            set_job_update(uuid.clone(), "not_running".to_string());
+            job_failed_notification(job_info.uuid);
+            return;
+        }
+    }
+
+    // Continue in the folder of the interrupted run without rotating copies
+    if let Some(run_dir) = resume_dir {
+        if !run_dir.exists() {
+            job_log(
+                uuid.clone().as_str(),
+                &format!(
+                    "Job failed. Output folder of interrupted run does not exist: '{}'.",
+                    run_dir.display()
+                ),
+                "ERROR",
+                log_level.clone(),
+            );
+            update_job_status(
+                uuid.as_str(),
+                2,
+                String::from("Job failed."),
+                String::from("Output folder of interrupted run does not exist."),
+                false,
+                true,
+                0.0,
+            );
+            set_job_update(uuid.clone(), "not_running".to_string());
+            journal_manager::clear_interrupted_run(&uuid);
+            job_failed_notification(job_info.uuid);
+            return;
+        }
+
+        job_log(
+            uuid.clone().as_str(),
+            &format!("Resuming interrupted run in '{}'", run_dir.display()),
+            "STEP",
+            log_level.clone(),
+        );
+        tauri::async_runtime::spawn(job_stage_three(uuid, files, run_dir, true));
+        return;
+    }
+
+    // Deduplicated stores write each run to a new snapshot instead of rotating copies
+    if job_info.storage_layout.clone().unwrap_or_default() == "dedup" {
+        let store_dir = dedup_manager::get_store_dir(&output_dir, &job_info.uuid);
+        dedup_manager::remove_incomplete_snapshots(&store_dir);
+        match dedup_manager::create_snapshot_dir(&store_dir) {
+            Ok(snapshot_dir) => {
+                println!("Created snapshot directory: {}", snapshot_dir.display());
+                tauri::async_runtime::spawn(job_stage_three(uuid, files, snapshot_dir, false));
+            }
+            Err(e) => {
+                job_log(
+                    uuid.clone().as_str(),
+                    &format!(
+                        "Job failed. Failed to create snapshot directory in '{}'.",
+                        store_dir.display()
+                    ),
+                    "ERROR",
+                    log_level.clone(),
+                );
+                println!("Failed to create snapshot directory: {}", e);
+                update_job_status(
+                    uuid.as_str(),
+                    2,
+                    String::from("Job failed."),
+                    String::from("Failed to create snapshot directory."),
+                    false,
+                    true,
+                    0.0,
+                );
+                set_job_update(uuid.clone(), "not_running".to_string());
+                job_failed_notification(job_info.uuid);
+            }
+        }
+        return;
+    }
+
     // Create job directory
     let mut output_dir_path = std::path::PathBuf::from(&output_dir);
     output_dir_path = output_dir_path.join(format!("archway-{}", job_info.uuid));
@@ -688,16 +1589,18 @@ async fn job_stage_two(uuid: String, files: Vec<String>) {
         }
     }
 
-    tauri::async_runtime::spawn(job_stage_three(uuid, files, output_dir_path));
+    tauri::async_runtime::spawn(job_stage_three(uuid, files, output_dir_path, false));
 }
 
 // Stage three of the job: Copying files
-async fn job_stage_three(uuid: String, files: Vec<String>, output_dir: PathBuf) {
+async fn job_stage_three(uuid: String, files: Vec<String>, output_dir: PathBuf, resuming: bool) {
     let log_level = settings_manager::get_settings().log_level.unwrap();
     let total_files = files.len() as u32;
     let mut output_paths: Vec<String> = Vec::new();
-    let mut processed_files = 0;
+    let mut processed_files: u32 = 0;
     let mut recovery_paths: Vec<Vec<String>> = Vec::new();
+    let mut manifest_entries: Vec<ManifestEntry> = Vec::new();
+    let mut skipped_files: Vec<bool> = Vec::new();
 
     update_job_status(
         uuid.as_str(),
@@ -719,85 +1622,301 @@ async fn job_stage_three(uuid: String, files: Vec<String>, output_dir: PathBuf)
     println!("Output directory: {}", output_dir.display());
 
     let job_info = storage_manager::get_job_by_uuid(&uuid);
-    let input_dirs_struct = job_info.input_dirs.clone();
+
+    // Encrypted jobs need their key before anything is written
+    let encryption_key = match encryption_manager::get_job_key(&job_info) {
+        Ok(key) => key,
+        Err(e) => {
+            println!("Failed to load encryption key: {}", e);
+            update_job_status(
+                uuid.as_str(),
+                3,
+                String::from("Job failed."),
+                format!("Could not load the encryption key: {}", e),
+                false,
+                true,
+                0.0,
+            );
+            job_log(
+                uuid.clone().as_str(),
+                &format!("Job failed. Could not load the encryption key: {}", e),
+                "ERROR",
+                log_level.clone(),
+            );
+            set_job_update(uuid.clone(), "not_running".to_string());
+            job_failed_notification(job_info.uuid);
+
+            return;
+        }
+    };
+    // The manifest and journal are encrypted whenever encryption.json is there, so it must match this run
+    if encryption_key.is_none() {
+        encryption_manager::remove_run_encryption(&output_dir);
+    } else if !encryption_manager::write_run_encryption(&output_dir, &job_info) {
+        println!("Failed to record encryption details in {}", output_dir.display());
+        update_job_status(
+            uuid.as_str(),
+            3,
+            String::from("Job failed."),
+            String::from("Could not record the encryption details in the output folder."),
+            false,
+            true,
+            0.0,
+        );
+        job_log(
+            uuid.clone().as_str(),
+            "Job failed. Could not record the encryption details in the output folder.",
+            "ERROR",
+            log_level.clone(),
+        );
+        set_job_update(uuid.clone(), "not_running".to_string());
+        job_failed_notification(job_info.uuid);
+
+        return;
+    }
+    let library_dirs = get_job_library_dirs(&job_info);
     let mut input_dirs_cleaned: Vec<String> = Vec::new();
 
-    for input_dir in input_dirs_struct {
+    for input_dir in job_info.input_dirs.clone() {
         if input_dir.path_type == "library" {
-            if input_dir.path == "documents" {
-                let documents_path = dirs::document_dir().unwrap();
-                input_dirs_cleaned.push(documents_path.to_string_lossy().to_string());
-            } else if input_dir.path == "downloads" {
-                let downloads_path = dirs::download_dir().unwrap();
-                input_dirs_cleaned.push(downloads_path.to_string_lossy().to_string());
-            } else if input_dir.path == "desktop" {
-                let desktop_path = dirs::desktop_dir().unwrap();
-                input_dirs_cleaned.push(desktop_path.to_string_lossy().to_string());
-            } else if input_dir.path == "music" {
-                let music_path = dirs::audio_dir().unwrap();
-                input_dirs_cleaned.push(music_path.to_string_lossy().to_string());
-            } else if input_dir.path == "pictures" {
-                let pictures_path = dirs::picture_dir().unwrap();
-                input_dirs_cleaned.push(pictures_path.to_string_lossy().to_string());
-            } else if input_dir.path == "videos" {
-                let videos_path = dirs::video_dir().unwrap();
-                input_dirs_cleaned.push(videos_path.to_string_lossy().to_string());
-            } else {
-                println!("Unknown library path: {}", input_dir.path);
+            match library_dirs.get(&input_dir.path) {
+                Some(library_path) => input_dirs_cleaned.push(library_path.clone()),
+                None => println!("Unknown library path: {}", input_dir.path),
             }
         } else {
             input_dirs_cleaned.push(input_dir.path.clone());
         }
     }
 
-    for file in &files {
-        if handle_pause_stop(uuid.clone()) {
-            return;
-        }
+    // Files are stored by hash when writing to a deduplicated store
+    let dedup_store_dir = dedup_manager::get_store_dir_from_snapshot(&output_dir);
+    let symlink_policy = link_manager::get_symlink_policy(&job_info);
+    let preserve_hardlinks = job_info.preserve_hardlinks.unwrap_or(true);
+    let error_policy = copy_manager::get_error_policy(&job_info);
+    // Files left out of the run, for jobs that continue on errors
+    let mut failed_indices: HashSet<usize> = HashSet::new();
+
+    let output_format = archive_manager::get_output_format(&job_info);
+    if output_format != "mirror" {
+        journal_manager::start_journal(&output_dir, &job_info.uuid, &files);
+        let result = if encryption_key.is_some() {
+            Err(String::from("Encrypted jobs can only use the mirror output format."))
+        } else {
+            // Archives are written on a blocking thread, so reading files and waiting retries do not hold up the async runtime
+            let (archive_uuid, archive_files, archive_input_dirs, archive_output_dir, archive_job_info, archive_log_level) = (
+                uuid.clone(),
+                files.clone(),
+                input_dirs_cleaned.clone(),
+                output_dir.clone(),
+                job_info.clone(),
+                log_level.clone(),
+            );
+            let archive_format = output_format.clone();
+            tauri::async_runtime::spawn_blocking(move || {
+                write_archive_run(
+                    archive_uuid.as_str(),
+                    &archive_files,
+                    &archive_input_dirs,
+                    &archive_output_dir,
+                    &archive_job_info,
+                    archive_format.as_str(),
+                    &archive_log_level,
+                )
+            })
+            .await
+            .unwrap_or_else(|e| {
+                println!("Archive thread failed: {}", e);
+                Err(String::from("Writing the archive stopped unexpectedly."))
+            })
+        };
+        let (output_paths, recovery_paths, manifest_entries) = match result {
+            Ok(Some(archived)) => archived,
+            Ok(None) => return,
+            Err(message) => {
+                update_job_status(
+                    uuid.as_str(),
+                    3,
+                    String::from("Job failed."),
+                    message.clone(),
+                    false,
+                    true,
+                    0.0,
+                );
+                job_log(
+                    uuid.clone().as_str(),
+                    &format!("Job failed. {}", message),
+                    "ERROR",
+                    log_level.clone(),
+                );
+                set_job_update(uuid.clone(), "not_running".to_string());
+                job_failed_notification(job_info.uuid);
 
-        let file_path = PathBuf::from(&file);
-        let mut file_path_str = file_path.to_string_lossy().to_string();
-        update_last_action(
-            uuid.as_str(),
-            format!(
-                "Copying file: {} ({}/{})",
-                file_path_str,
-                processed_files + 1,
-                total_files
+                return;
+            }
+        };
+        write_recovery_paths(&output_dir, &recovery_paths, None).unwrap();
+
+        // Every archived file is verified against its volume in stage four, leaving out files that could not be read
+        let files: Vec<String> = manifest_entries.iter().map(|entry| entry.source.clone()).collect();
+        let skipped_files = vec![false; files.len()];
+        tauri::async_runtime::spawn(job_stage_four(
+            uuid,
+            files,
+            output_paths,
+            output_dir,
+            manifest_entries,
+            recovery_paths,
+            skipped_files,
+        ));
+        return;
+    }
+
+    // Load the previous run's manifest so unchanged files can be skipped
+    let incremental = job_info.incremental.clone().unwrap_or("off".to_string());
+    let mut previous_run_dir: Option<PathBuf> = None;
+    let mut previous_entries: HashMap<String, ManifestEntry> = HashMap::new();
+    if job_info.file_behavior == "copy" && incremental != "off" {
+        previous_run_dir = manifest_manager::get_previous_run_dir(&output_dir, &job_info.uuid);
+        if let Some(previous_manifest) = previous_run_dir
+            .as_ref()
+            .and_then(|dir| manifest_manager::read_manifest(dir))
+        {
+            for entry in previous_manifest.files {
+                previous_entries.insert(entry.source.clone(), entry);
+            }
+        }
+        job_log(
+            uuid.clone().as_str(),
+            &format!(
+                "Incremental mode '{}', {} files in previous manifest",
+                incremental,
+                previous_entries.len()
             ),
+            "STEP",
+            log_level.clone(),
         );
+    }
+
+    // Files copied and verified before the run was interrupted
+    let mut copied_before: HashSet<String> = HashSet::new();
+    let mut copied_hashes: HashMap<String, String> = HashMap::new();
+    let mut verified_before: HashMap<String, String> = HashMap::new();
+    if resuming {
+        if let Some(progress) = journal_manager::read_journal(&output_dir) {
+            copied_before = progress.copied;
+            copied_hashes = progress.copied_hashes;
+            verified_before = progress.verified;
+        }
         job_log(
             uuid.clone().as_str(),
-            &format!("Copying file: {}", file_path_str),
-            "FILE",
+            &format!(
+                "Resuming run, {} files already copied, {} files already verified",
+                copied_before.len(),
+                verified_before.len()
+            ),
+            "STEP",
             log_level.clone(),
         );
+    } else {
+        journal_manager::start_journal(&output_dir, &job_info.uuid, &files);
+    }
 
-        // Remove the input directory from the file path so the directory structure is preserved
-        let longest_matching_dir = input_dirs_cleaned
-            .iter()
-            .filter(|input_dir| file_path_str.starts_with(*input_dir))
-            .max_by_key(|input_dir| input_dir.len());
-        if let Some(longest_dir) = longest_matching_dir {
-            // Keep the last child directory in the path
-
-            let mut parts: Vec<&str> = Vec::new();
+    // Work out where each file goes, leaving the copying and verifying to the copy workers
+    let mut copy_tasks: Vec<CopyTask> = Vec::new();
+    // The first file copied for each set of hard links, and the files to link to it once it is copied
+    let mut hardlink_copies: HashMap<link_manager::FileId, usize> = HashMap::new();
+    let mut hardlinks: Vec<(usize, usize)> = Vec::new();
+    for (index, file) in files.iter().enumerate() {
+        if handle_pause_stop(uuid.clone()) {
+            return;
+        }
 
-            if longest_dir.contains('\\') {
-                parts = longest_dir.split('\\').collect();
-            } else if longest_dir.contains('/') {
-                parts = longest_dir.split('/').collect();
-            }
+        let file_path_str = get_relative_output_path(file, &input_dirs_cleaned);
 
-            // Get the last part of the path
-            let last_part = parts.pop().unwrap();
+        println!("File path: {}", file_path_str);
+        println!("Output directory: {}", output_dir.display());
 
-            // Remove the longest matching input directory from the file path
-            file_path_str = last_part.to_string() + &file_path_str.replace(longest_dir, "");
+        // Links kept as links are recreated in mirrored runs, and recorded in the manifest for recovery
+        if symlink_policy == "link" {
+            if let Some(link_target) = link_manager::read_link_target(file) {
+                let output_file = output_dir.join(&file_path_str);
+                if dedup_store_dir.is_none() {
+                    let result = output_file
+                        .parent()
+                        .map_or(Ok(()), fs::create_dir_all)
+                        .and_then(|_| link_manager::create_symlink(&link_target, &output_file));
+                    if let Err(e) = result {
+                        let message = format!(
+                            "Could not create link {}, it is only recorded in the manifest: {}",
+                            output_file.display(),
+                            e
+                        );
+                        println!("{}", message);
+                        job_log(uuid.clone().as_str(), &message, "FILE", log_level.clone());
+                    }
+                }
+                output_paths.push(output_file.to_string_lossy().to_string());
+                recovery_paths.push(vec![output_file.to_string_lossy().to_string(), file.to_string()]);
+                manifest_entries.push(get_link_manifest_entry(file, file_path_str.clone(), link_target));
+                skipped_files.push(true);
+                processed_files += 1;
+                update_job_progress(uuid.as_str(), processed_files as f32 / total_files as f32);
+                update_last_action(
+                    uuid.as_str(),
+                    format!("Linked: {} ({}/{})", file_path_str, processed_files, total_files),
+                );
+                continue;
+            }
         }
 
-        println!("File path: {}", file_path_str);
-        println!("Output directory: {}", output_dir.display());
+        let file_metadata = metadata_manager::read_metadata(file, &job_info);
+
+        if let Some(store_dir) = dedup_store_dir.as_ref() {
+            let (file_size, file_modified) =
+                manifest_manager::get_file_fingerprint(file).unwrap_or((0, 0));
+
+            // Reuse the previous hash for unchanged files instead of reading them again
+            let previous_hash = previous_entries
+                .get(file)
+                .filter(|entry| {
+                    incremental == "metadata"
+                        && entry.size == file_size
+                        && entry.modified == file_modified
+                })
+                .and_then(|entry| entry.hash.clone());
+
+            let previous_stored_hash = previous_entries
+                .get(file)
+                .filter(|entry| previous_hash.is_some() && entry.hash == previous_hash)
+                .and_then(|entry| entry.stored_hash.clone());
+
+            // The blob path is filled in once a copy worker has hashed the file
+            output_paths.push(String::new());
+            recovery_paths.push(vec![String::new(), file.to_string()]);
+            manifest_entries.push(ManifestEntry {
+                source: file.to_string(),
+                output: file_path_str.clone(),
+                size: file_size,
+                modified: file_modified,
+                hash: previous_hash.clone(),
+                stored_hash: None,
+                metadata: file_metadata,
+                link_target: None,
+            });
+            skipped_files.push(false);
+            copy_tasks.push(CopyTask {
+                index,
+                source: file.to_string(),
+                display_path: file_path_str,
+                output: None,
+                blob: Some((store_dir.clone(), previous_hash)),
+                known_stored_hash: previous_stored_hash,
+                metadata: None,
+                // Blobs copied before an interruption still need verifying, other existing blobs are already stored
+                unverified_copy: copied_before.contains(file) && !verified_before.contains_key(file),
+            });
+            continue;
+        }
 
         // Ensure the output directory exists
         let output_file_full_path = output_dir.join(&file_path_str);
@@ -810,6 +1929,15 @@ async fn job_stage_three(uuid: String, files: Vec<String>, output_dir: PathBuf)
                     "Created output directory: {}",
                     output_file_parent.as_ref().unwrap().display()
                 ),
+                Err(e) if error_policy.continue_on_error => {
+                    skip_failed_file(
+                        uuid.as_str(),
+                        file,
+                        &format!("Failed to create output directory: {}", e),
+                        &log_level,
+                    );
+                    failed_indices.insert(index);
+                }
                 Err(e) => {
                     println!("Failed to create output directory: {}", e);
                     update_job_status(
@@ -838,40 +1966,328 @@ async fn job_stage_three(uuid: String, files: Vec<String>, output_dir: PathBuf)
             }
         }
 
-        // Copy the file
         let output_file = output_dir.join(&file_path_str);
         // Store recovery path
         recovery_paths.push(vec![
             output_file_full_path.to_str().unwrap().to_string(),
             file.to_string()
         ]);
-        match std::fs::copy(&file, &output_file) {
-            Ok(_) => {
+
+        let (file_size, file_modified) =
+            manifest_manager::get_file_fingerprint(file).unwrap_or((0, 0));
+        let mut manifest_entry = ManifestEntry {
+            source: file.to_string(),
+            output: output_file
+                .strip_prefix(&output_dir)
+                .unwrap_or(&output_file)
+                .to_string_lossy()
+                .to_string(),
+            size: file_size,
+            modified: file_modified,
+            hash: None,
+            stored_hash: None,
+            metadata: file_metadata.clone(),
+            link_target: None,
+        };
+
+        if failed_indices.contains(&index) {
+            manifest_entries.push(manifest_entry);
+            skipped_files.push(false);
+            continue;
+        }
+
+        // Skip files that were already copied before the run was interrupted
+        if copied_before.contains(file) && output_file.exists() {
+            // Copies that were not verified yet are checked against the hash recorded while copying
+            manifest_entry.hash = verified_before
+                .get(file)
+                .or_else(|| copied_hashes.get(file))
+                .cloned();
+            manifest_entries.push(manifest_entry);
+            skipped_files.push(verified_before.contains_key(file));
+            processed_files += 1;
+            let percent = processed_files as f32 / total_files as f32;
+            update_job_progress(uuid.as_str(), percent);
+            update_last_action(
+                uuid.as_str(),
+                format!(
+                    "Already copied file: {} ({}/{})",
+                    file_path_str, processed_files, total_files
+                ),
+            );
+            continue;
+        }
+
+        // Skip files that have not changed since the previous run
+        if let (Some(previous_entry), Some(previous_dir)) =
+            (previous_entries.get(file), previous_run_dir.as_ref())
+        {
+            // Outputs are only reused when they are encrypted the same way, and under the same salt, as this run's
+            if is_file_unchanged(file, previous_entry, incremental.as_str())
+                && previous_entry.stored_hash.is_some() == encryption_key.is_some()
+                && encryption_manager::is_stored_with(&previous_dir.join(&previous_entry.output), encryption_key.as_ref())
+                && manifest_manager::reuse_previous_output(previous_entry, previous_dir, &output_file)
+            {
+                manifest_entry.hash = previous_entry.hash.clone();
+                manifest_entry.stored_hash = previous_entry.stored_hash.clone();
+                apply_file_metadata(uuid.as_str(), &output_file, file_metadata.as_ref(), &log_level);
+                // Entries from manifests without hashes are left out of the journal, so a resumed run checks them again
+                if let Some(hash) = manifest_entry.hash.as_ref() {
+                    journal_manager::record_verified(&output_dir, file, hash);
+                }
+                manifest_entries.push(manifest_entry);
+                skipped_files.push(true);
+                increment_skipped_files(uuid.as_str());
                 processed_files += 1;
                 let percent = processed_files as f32 / total_files as f32;
                 update_job_progress(uuid.as_str(), percent);
                 update_last_action(
                     uuid.as_str(),
                     format!(
-                        "Copied file: {} ({}/{})",
+                        "Skipped unchanged file: {} ({}/{})",
                         file_path_str, processed_files, total_files
                     ),
                 );
+                job_log(
+                    uuid.clone().as_str(),
+                    &format!("Skipped unchanged file: {}", file_path_str),
+                    "FILE",
+                    log_level.clone(),
+                );
+                continue;
+            }
+        }
+
+        // Further hard links to a file are linked to its copy once the copy workers are done
+        if preserve_hardlinks {
+            if let Some(hardlink_id) = link_manager::get_hardlink_id(file) {
+                if let Some(first_index) = hardlink_copies.get(&hardlink_id) {
+                    hardlinks.push((index, *first_index));
+                    manifest_entries.push(manifest_entry);
+                    skipped_files.push(false);
+                    continue;
+                }
+                hardlink_copies.insert(hardlink_id, index);
+            }
+        }
+
+        manifest_entries.push(manifest_entry);
+        skipped_files.push(false);
+        copy_tasks.push(CopyTask {
+            index,
+            source: file.to_string(),
+            display_path: file_path_str,
+            output: Some(output_file),
+            blob: None,
+            known_stored_hash: None,
+            metadata: file_metadata,
+            unverified_copy: false,
+        });
+    }
+
+    // Copy and verify the remaining files on the copy workers
+    let worker_count = copy_manager::get_worker_count();
+    job_log(
+        uuid.clone().as_str(),
+        &format!(
+            "Copying {} files with {} workers",
+            copy_tasks.len(),
+            worker_count
+        ),
+        "STEP",
+        log_level.clone(),
+    );
+    // The pool runs on a blocking thread, so copying files and waiting retries do not hold up the async runtime
+    let (pool_uuid, pool_output_dir, pool_key, pool_log_level, pool_policy) = (
+        uuid.clone(),
+        output_dir.clone(),
+        encryption_key.clone(),
+        log_level.clone(),
+        copy_manager::get_error_policy(&job_info),
+    );
+    let pool = tauri::async_runtime::spawn_blocking(move || {
+        let (uuid, output_dir, encryption_key, log_level, error_policy) =
+            (pool_uuid, pool_output_dir, pool_key, pool_log_level, pool_policy);
+        let processed_counter = AtomicU32::new(processed_files);
+        let results = copy_manager::run_tasks(
+            &copy_tasks,
+            worker_count,
+            || handle_pause_stop(uuid.clone()),
+            |task| {
+                // Pausing and stopping are handled by the pool, so retries only watch for a stop request
+                let result = copy_manager::retry(
+                    &error_policy,
+                    || is_stop_requested(uuid.as_str()),
+                    || run_copy_task(uuid.as_str(), task, &output_dir, encryption_key.as_ref(), &log_level),
+                );
+                let processed = processed_counter.fetch_add(1, Ordering::SeqCst) + 1;
+                update_job_progress(uuid.as_str(), processed as f32 / total_files as f32);
+                let outcome = match result {
+                    Ok(outcome) => outcome,
+                    Err(message) if error_policy.continue_on_error && !is_stop_requested(uuid.as_str()) => {
+                        skip_failed_file(uuid.as_str(), &task.source, &message, &log_level);
+                        journal_manager::record_failed(&output_dir, &task.source);
+                        // Partly written copies are removed, since the file is not part of the run
+                        if let Some(output) = task.output.as_ref() {
+                            let _ = fs::remove_file(output);
+                        }
+                        return Ok(None);
+                    }
+                    Err(message) => return Err(message),
+                };
+                update_last_action(
+                    uuid.as_str(),
+                    format!(
+                        "{} file: {} ({}/{})",
+                        if outcome.already_stored { "Already stored" } else { "Copied" },
+                        task.display_path,
+                        processed,
+                        total_files
+                    ),
+                );
+                Ok(Some(outcome))
+            },
+        );
+        (copy_tasks, results)
+    })
+    .await;
+    let (copy_tasks, results) = match pool {
+        Ok(pool) => pool,
+        Err(e) => {
+            println!("Copy thread failed: {}", e);
+            update_job_status(
+                uuid.as_str(),
+                3,
+                String::from("Job failed."),
+                String::from("Copying stopped unexpectedly."),
+                false,
+                true,
+                0.0,
+            );
+            job_log(
+                uuid.clone().as_str(),
+                "Job failed. Copying stopped unexpectedly.",
+                "ERROR",
+                log_level.clone(),
+            );
+            set_job_update(uuid.clone(), "not_running".to_string());
+            job_failed_notification(job_info.uuid);
+            return;
+        }
+    };
+
+    // Retries give up once the job is stopped, which is reported as a stop rather than a failure
+    if is_stop_requested(uuid.as_str()) {
+        handle_pause_stop(uuid.clone());
+        return;
+    }
+    for result in results.iter() {
+        if let Some(Err(message)) = result {
+            update_job_status(
+                uuid.as_str(),
+                3,
+                String::from("Job failed."),
+                message.clone(),
+                false,
+                true,
+                0.0,
+            );
+            job_log(
+                uuid.clone().as_str(),
+                &format!("Job failed. {}", message),
+                "ERROR",
+                log_level.clone(),
+            );
+            set_job_update(uuid.clone(), "not_running".to_string());
+            job_failed_notification(job_info.uuid);
+
+            return;
+        }
+    }
+    // Files are only left out when the job was stopped
+    if results.iter().any(|result| result.is_none()) {
+        return;
+    }
+
+    for (task, result) in copy_tasks.iter().zip(results) {
+        if let Some(Ok(None)) = result {
+            failed_indices.insert(task.index);
+        }
+        if let Some(Ok(Some(outcome))) = result {
+            if task.blob.is_some() {
+                output_paths[task.index] = outcome.output.to_string_lossy().to_string();
+                recovery_paths[task.index][0] = outcome.output.to_string_lossy().to_string();
+            }
+            manifest_entries[task.index].hash = Some(outcome.hash);
+            manifest_entries[task.index].stored_hash = outcome.stored_hash;
+            // Files that failed verification while copying are checked again against the source in stage four
+            skipped_files[task.index] = outcome.verified;
+        }
+    }
+
+    for (index, first_index) in hardlinks {
+        if failed_indices.contains(&first_index) {
+            skip_failed_file(
+                uuid.as_str(),
+                &files[index],
+                &format!("It is a hard link to {}, which could not be copied", files[first_index]),
+                &log_level,
+            );
+            failed_indices.insert(index);
+            continue;
+        }
+        let first_output = PathBuf::from(&output_paths[first_index]);
+        let output = PathBuf::from(&output_paths[index]);
+        match link_manager::link_or_copy(&first_output, &output) {
+            Ok(linked) => {
+                manifest_entries[index].hash = manifest_entries[first_index].hash.clone();
+                manifest_entries[index].stored_hash = manifest_entries[first_index].stored_hash.clone();
+                // Copies made where hard links are not supported are checked against the source in stage four
+                skipped_files[index] = linked && skipped_files[first_index];
+                if !linked {
+                    apply_file_metadata(uuid.as_str(), &output, manifest_entries[index].metadata.as_ref(), &log_level);
+                }
+                if let Some(hash) = manifest_entries[index].hash.as_ref() {
+                    journal_manager::record_copied(&output_dir, &files[index], hash);
+                    if skipped_files[index] {
+                        journal_manager::record_verified(&output_dir, &files[index], hash);
+                    }
+                }
+                job_log(
+                    uuid.clone().as_str(),
+                    &format!(
+                        "{} {} to {}",
+                        if linked { "Hard linked" } else { "Copied" },
+                        output.display(),
+                        first_output.display()
+                    ),
+                    "FILE",
+                    log_level.clone(),
+                );
+            }
+            Err(e) if error_policy.continue_on_error => {
+                skip_failed_file(
+                    uuid.as_str(),
+                    &files[index],
+                    &format!("Failed to link {} to {}: {}", output.display(), first_output.display(), e),
+                    &log_level,
+                );
+                failed_indices.insert(index);
             }
             Err(e) => {
-                println!("Failed to copy file {}: {}", file, e);
+                println!("Failed to link {} to {}: {}", output.display(), first_output.display(), e);
                 update_job_status(
                     uuid.as_str(),
                     3,
                     String::from("Job failed."),
-                    format!("Failed to copy file: {}", file),
+                    format!("Failed to copy file: {}", files[index]),
                     false,
                     true,
                     0.0,
                 );
                 job_log(
                     uuid.clone().as_str(),
-                    &format!("Job failed. Failed to copy file: {}", file),
+                    &format!("Job failed. Failed to link {} to {}: {}", output.display(), first_output.display(), e),
                     "ERROR",
                     log_level.clone(),
                 );
@@ -882,15 +2298,50 @@ async fn job_stage_three(uuid: String, files: Vec<String>, output_dir: PathBuf)
             }
         }
     }
+    // Files that could not be copied are left out of the run
+    let files = remove_failed(files, &failed_indices);
+    let output_paths = remove_failed(output_paths, &failed_indices);
+    let recovery_paths = remove_failed(recovery_paths, &failed_indices);
+    let manifest_entries = remove_failed(manifest_entries, &failed_indices);
+    let skipped_files = remove_failed(skipped_files, &failed_indices);
 
     // Save recovery paths
-    storage_manager::write_json_file(output_dir.join("recovery_paths.json").to_str().unwrap().to_string(), &recovery_paths).unwrap();
+    write_recovery_paths(&output_dir, &recovery_paths, encryption_key.as_ref()).unwrap();
+
+    tauri::async_runtime::spawn(job_stage_four(
+        uuid,
+        files,
+        output_paths,
+        output_dir,
+        manifest_entries,
+        recovery_paths,
+        skipped_files,
+    ));
+}
 
-    tauri::async_runtime::spawn(job_stage_four(uuid, files, output_paths));
+// Writes a run's recovery file. It lists every backed up path, so it is encrypted along with the files.
+fn write_recovery_paths(output_dir: &Path, recovery_paths: &[Vec<String>], encryption_key: Option<&EncryptionKey>) -> io::Result<()> {
+    let recovery_file = output_dir.join("recovery_paths.json");
+    match encryption_key {
+        Some(key) => encryption_manager::encrypt_bytes_to_file(
+            &recovery_file,
+            storage_manager::to_json_string(&recovery_paths).as_bytes(),
+            key,
+        ),
+        None => storage_manager::write_json_file(recovery_file.to_string_lossy().to_string(), &recovery_paths),
+    }
 }
 
 // Stage four of the job: Verifying files
-async fn job_stage_four(uuid: String, input_files: Vec<String>, output_files: Vec<String>) {
+async fn job_stage_four(
+    uuid: String,
+    mut input_files: Vec<String>,
+    mut output_files: Vec<String>,
+    output_dir: PathBuf,
+    mut manifest_entries: Vec<ManifestEntry>,
+    mut recovery_paths: Vec<Vec<String>>,
+    skipped_files: Vec<bool>,
+) {
     let log_level = settings_manager::get_settings().log_level.unwrap();
     update_job_status(
         uuid.as_str(),
@@ -935,12 +2386,24 @@ async fn job_stage_four(uuid: String, input_files: Vec<String>, output_files: Ve
     let mut verified_files = 0;
     let total_files = input_files.len() as u32;
     let mut failed_files: Vec<String> = Vec::new();
+    let mut failed_indices: HashSet<usize> = HashSet::new();
+    // A missing key fails verification of encrypted files, since their contents cannot be read
+    let encryption_key = encryption_manager::get_job_key(&storage_manager::get_job_by_uuid(&uuid)).unwrap_or(None);
+    // Files in archives are verified by reading each volume once
+    let archived_hashes = archive_manager::hash_archived_files(&output_files);
 
     // Iterate through input and output files to verify the hashes match
-    for (input_file, output_file) in input_files.iter().zip(output_files.iter()) {
+    for (index, (input_file, output_file)) in input_files.iter().zip(output_files.iter()).enumerate() {
         if handle_pause_stop(uuid.clone()) {
             return;
         }
+
+        // Unchanged files, files verified while copying or before an interruption, and links kept as links
+        // do not need verifying again
+        if skipped_files[index] || manifest_entries[index].link_target.is_some() {
+            verified_files += 1;
+            continue;
+        }
         update_last_action(
             uuid.as_str(),
             format!(
@@ -961,7 +2424,8 @@ async fn job_stage_four(uuid: String, input_files: Vec<String>, output_files: Ve
             log_level.clone(),
         );
 
-        if !output_file_path.exists() {
+        let archived_hash = archived_hashes.get(output_file);
+        if archived_hash.is_none() && !output_file_path.exists() {
             job_log(
                 uuid.clone().as_str(),
                 &format!(
@@ -973,14 +2437,35 @@ async fn job_stage_four(uuid: String, input_files: Vec<String>, output_files: Ve
             );
             println!("Output file does not exist: {}", output_file);
             failed_files.push(output_file.clone());
+            failed_indices.insert(index);
             continue;
         }
 
-        match compare_files(
-            input_file_path.to_str().unwrap(),
-            output_file_path.to_str().unwrap(),
-        ) {
-            Ok(true) => {
+        // Files hashed while they were copied only need the output read again
+        let input_hash = match manifest_entries[index].hash.clone() {
+            Some(input_hash) => Ok(input_hash),
+            None => file_hash(input_file_path.to_str().unwrap())
+                .map(|input_hash| manifest_manager::hash_to_hex(&input_hash)),
+        };
+        let comparison = input_hash.and_then(|input_hash| {
+            let output_hash = match archived_hash {
+                Some(output_hash) => Ok(output_hash.clone()),
+                None => hash_output(&output_file_path, encryption_key.as_ref()),
+            };
+            output_hash.map(|output_hash| {
+                (manifest_manager::hash_to_hex(&output_hash) == input_hash, input_hash)
+            })
+        });
+
+        match comparison {
+            Ok((true, input_hash)) => {
+                journal_manager::record_verified(&output_dir, input_file, input_hash.as_str());
+                manifest_entries[index].hash = Some(input_hash);
+                if encryption_key.is_some() && manifest_entries[index].stored_hash.is_none() {
+                    manifest_entries[index].stored_hash = file_hash(output_file)
+                        .ok()
+                        .map(|stored_hash| manifest_manager::hash_to_hex(&stored_hash));
+                }
                 job_log(
                     uuid.clone().as_str(),
                     "Verified. ",
@@ -998,8 +2483,9 @@ async fn job_stage_four(uuid: String, input_files: Vec<String>, output_files: Ve
                     ),
                 );
             }
-            Ok(false) => {
+            Ok((false, _)) => {
                 println!("File verification failed for: {}", output_file);
+                journal_manager::record_failed(&output_dir, input_file);
                 job_log(
                     uuid.clone().as_str(),
                     &format!("Files do not match."),
@@ -1008,6 +2494,7 @@ async fn job_stage_four(uuid: String, input_files: Vec<String>, output_files: Ve
                 );
 
                 failed_files.push(output_file.clone());
+                failed_indices.insert(index);
             }
             Err(e) => {
                 job_log(
@@ -1018,41 +2505,132 @@ async fn job_stage_four(uuid: String, input_files: Vec<String>, output_files: Ve
                 );
                 println!("Error comparing files: {}", e);
                 failed_files.push(output_file.clone());
+                failed_indices.insert(index);
             }
         }
     }
 
+    // Jobs that continue on errors leave the files that failed verification out of the run,
+    // so they are copied again by the next run and never deleted by move jobs
+    let job_info = storage_manager::get_job_by_uuid(&uuid);
+    if !failed_files.is_empty() && copy_manager::get_error_policy(&job_info).continue_on_error {
+        let store_dir = dedup_manager::get_store_dir_from_snapshot(&output_dir);
+        for (index, input_file) in input_files.iter().enumerate() {
+            if failed_indices.contains(&index) {
+                skip_failed_file(uuid.as_str(), input_file, "The copy failed verification", &log_level);
+                // Mirrored copies are removed so they are not restored, while blobs and volumes may hold other files
+                let output_file = Path::new(&output_files[index]);
+                if store_dir.is_none() && archive_manager::split_archive_path(output_file).is_none() {
+                    let _ = fs::remove_file(output_file);
+                }
+            }
+        }
+        input_files = remove_failed(input_files, &failed_indices);
+        output_files = remove_failed(output_files, &failed_indices);
+        manifest_entries = remove_failed(manifest_entries, &failed_indices);
+        recovery_paths = remove_failed(recovery_paths, &failed_indices);
+        failed_files.clear();
+
+        // The recovery file was written in stage three, so it would still list the files left out
+        let result = encryption_manager::get_encrypted_run_key(&output_dir)
+            .map_err(io::Error::other)
+            .and_then(|run_key| write_recovery_paths(&output_dir, &recovery_paths, run_key.as_ref()));
+        if let Err(e) = result {
+            println!("Failed to rewrite recovery paths: {}", e);
+            job_log(
+                uuid.clone().as_str(),
+                &format!("Could not remove skipped files from the recovery file: {}", e),
+                "ERROR",
+                log_level.clone(),
+            );
+        }
+    }
+
     if failed_files.is_empty() {
-        let job_info = storage_manager::get_job_by_uuid(&uuid);
+        // Save the manifest so the next run can skip unchanged files
+        let manifest = RunManifest {
+            job_uuid: job_info.uuid.clone(),
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or(0),
+            files: manifest_entries,
+            libraries: Some(get_job_library_dirs(&job_info)),
+        };
+        manifest_manager::write_manifest(&output_dir, &manifest);
+        manifest_manager::write_checksums(&output_dir, &manifest.files, &output_files);
+
+        // Remove snapshots beyond the number of copies to keep, then any blobs they alone used
+        if let Some(store_dir) = dedup_manager::get_store_dir_from_snapshot(&output_dir) {
+            if job_info.file_behavior == "copy" {
+                let removed_snapshots = dedup_manager::prune_snapshots(&store_dir, job_info.copies);
+                let removed_blobs = dedup_manager::remove_unreferenced_blobs(&store_dir);
+                job_log(
+                    uuid.clone().as_str(),
+                    &format!(
+                        "Removed {} old snapshots and {} unreferenced files from the store",
+                        removed_snapshots, removed_blobs
+                    ),
+                    "STEP",
+                    log_level.clone(),
+                );
+            }
+        }
+
         if job_info.file_behavior == "move" {
             // If moving files, delete the original files
-            tauri::async_runtime::spawn(job_stage_five(uuid.clone(), input_files));
+            tauri::async_runtime::spawn(job_stage_five(uuid.clone(), input_files, output_dir));
         } else {
             println!("All files verified successfully.");
+            journal_manager::finish_journal(&output_dir, &uuid);
             set_job_update(uuid.clone(), "not_running".to_string());
 
+            let skipped_count = get_skipped_files(uuid.as_str());
+            let failed_files = get_failed_files(uuid.as_str());
             update_job_status(
                 uuid.as_str(),
                 4,
                 String::from("Job completed."),
-                String::from("All files verified successfully."),
+                if !failed_files.is_empty() {
+                    describe_failed_files(&failed_files)
+                } else if skipped_count > 0 {
+                    format!(
+                        "All files verified successfully. {} unchanged files skipped.",
+                        skipped_count
+                    )
+                } else {
+                    String::from("All files verified successfully.")
+                },
                 true,
                 true,
                 1.0,
             );
             job_log(
                 uuid.clone().as_str(),
-                "Job completed successfully.",
+                &if failed_files.is_empty() {
+                    String::from("Job completed successfully.")
+                } else {
+                    format!(
+                        "Job completed with {} warnings. Skipped files: {:?}",
+                        failed_files.len(),
+                        failed_files
+                    )
+                },
                 "STOP",
                 log_level.clone(),
             );
 
-            set_job_health_by_uuid(uuid.as_str(), "good");
+            // Files left out of the run leave the backup incomplete
+            set_job_health_by_uuid(uuid.as_str(), if failed_files.is_empty() { "good" } else { "degraded" });
             get_app_handle()
                 .notification()
                 .builder()
                 .title("Job Complete: ".to_owned() + &job_info.job_name)
-                .body("The job has been completed.")
+                .body(if failed_files.is_empty() {
+                    "The job has been completed."
+                } else {
+                    "The job has been completed with warnings."
+                })
                 .show()
                 .unwrap();
         }
@@ -1085,7 +2663,7 @@ async fn job_stage_four(uuid: String, input_files: Vec<String>, output_files: Ve
 }
 
 // Stage five of the job: Deleting original files (if moving files)
-async fn job_stage_five(uuid: String, input_files: Vec<String>) {
+async fn job_stage_five(uuid: String, input_files: Vec<String>, output_dir: PathBuf) {
     let log_level = settings_manager::get_settings().log_level.unwrap();
     update_job_status(
         uuid.as_str(),
@@ -1137,30 +2715,50 @@ async fn job_stage_five(uuid: String, input_files: Vec<String>) {
         }
     }
 
+    journal_manager::finish_journal(&output_dir, &uuid);
     set_job_update(uuid.clone(), "not_running".to_string());
+    // Files left out of the run were not deleted, since they were never backed up
+    let failed_files = get_failed_files(uuid.as_str());
     update_job_status(
         uuid.as_str(),
         5,
         String::from("Job completed."),
-        String::from("All original files deleted successfully."),
+        if failed_files.is_empty() {
+            String::from("All original files deleted successfully.")
+        } else {
+            describe_failed_files(&failed_files)
+        },
         true,
         true,
         1.0,
     );
     job_log(
         uuid.clone().as_str(),
-        "Job completed successfully. All original files deleted.",
+        &if failed_files.is_empty() {
+            String::from("Job completed successfully. All original files deleted.")
+        } else {
+            format!(
+                "Job completed with {} warnings. Skipped files were not deleted: {:?}",
+                failed_files.len(),
+                failed_files
+            )
+        },
         "STOP",
         log_level.clone(),
     );
 
-    set_job_health_by_uuid(uuid.as_str(), "good");
+    // Files left out of the run leave the backup incomplete
+    set_job_health_by_uuid(uuid.as_str(), if failed_files.is_empty() { "good" } else { "degraded" });
 
     get_app_handle()
         .notification()
         .builder()
         .title("Job Complete: ".to_owned() + &job_info.job_name)
-        .body("The job has been completed.")
+        .body(if failed_files.is_empty() {
+            "The job has been completed."
+        } else {
+            "The job has been completed with warnings."
+        })
         .show()
         .unwrap();
 }
@@ -1178,11 +2776,23 @@ fn job_failed_notification(uuid: String) {
         .unwrap();
 }
 
+pub fn is_job_running(uuid: &str) -> bool {
+    let job_statuses = JOB_STATUSES.lock().unwrap();
+    job_statuses
+        .iter()
+        .any(|js| js.job.uuid == uuid && !js.completed)
+}
+
 pub fn get_active_jobs() -> u8 {
     let job_statuses = JOB_STATUSES.lock().unwrap();
     job_statuses.iter().filter(|js| !js.completed).count() as u8
 }
 
+// Checks whether the job was asked to stop or already stopped, without handling the request
+fn is_stop_requested(uuid: &str) -> bool {
+    matches!(get_job_update(uuid.to_string()).as_str(), "stop_requested" | "not_running")
+}
+
 fn handle_pause_stop(uuid: String) -> bool {
     if get_job_update(uuid.clone()) == "pause_requested" {
         set_job_update(uuid.clone(), "paused".to_string());
@@ -0,0 +1,250 @@
+use crate::encryption_manager;
+use crate::encryption_manager::EncryptionKey;
+use crate::storage_manager::{file_with_executable, from_json_string, read_json_file, to_json_string, write_json_file};
+use crate::structs::RunJournal;
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, HashSet};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/*
+Each run keeps a journal in its output folder:
+- journal.json holds the indexed file list, written once when copying starts
+- journal.log holds one line per file event, appended as the run progresses
+    copied\t<hash>\t<source path>
+    verified\t<hash>\t<source path>
+    failed\t<source path>
+The hash of a copied file is the hash of the source as it was read, so a resumed run can
+verify the copy without reading the source again.
+The journal is removed once the run completes, so a journal left behind marks an interrupted run.
+Journals of encrypted runs are encrypted with the run's key: journal.json as a whole, and
+journal.log line by line, each line written as hex.
+ */
+
+// Copy workers record files at the same time, so lines are appended one at a time
+static JOURNAL_LOCK: Mutex<()> = Mutex::new(());
+
+// The key of each run's journal, so the key is not looked up again for every line
+static JOURNAL_KEYS: Lazy<Mutex<HashMap<PathBuf, Option<EncryptionKey>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+pub struct JournalProgress {
+    pub journal: RunJournal,
+    pub copied: HashSet<String>,
+    pub copied_hashes: HashMap<String, String>,
+    pub verified: HashMap<String, String>,
+}
+
+fn get_journal_path(run_dir: &Path) -> PathBuf {
+    run_dir.join("journal.json")
+}
+
+fn get_journal_log_path(run_dir: &Path) -> PathBuf {
+    run_dir.join("journal.log")
+}
+
+fn get_journal_key(run_dir: &Path) -> Result<Option<EncryptionKey>, String> {
+    let mut keys = JOURNAL_KEYS.lock().unwrap();
+    if let Some(key) = keys.get(run_dir) {
+        return Ok(key.clone());
+    }
+    let key = encryption_manager::get_encrypted_run_key(run_dir)?;
+    keys.insert(run_dir.to_path_buf(), key.clone());
+    Ok(key)
+}
+
+fn get_all_interrupted_runs() -> HashMap<String, String> {
+    read_json_file::<HashMap<String, String>>(file_with_executable("job_journals.json"))
+        .unwrap_or_else(|_| HashMap::new())
+}
+
+fn set_all_interrupted_runs(runs: HashMap<String, String>) -> bool {
+    write_json_file(file_with_executable("job_journals.json"), &runs).is_ok()
+}
+
+// Gets the output folder of a job's interrupted run, if its journal is still there
+pub fn get_interrupted_run(job_uuid: &str) -> Option<PathBuf> {
+    let run_dir = PathBuf::from(get_all_interrupted_runs().get(job_uuid)?);
+    if get_journal_path(&run_dir).exists() {
+        Some(run_dir)
+    } else {
+        None
+    }
+}
+
+pub fn clear_interrupted_run(job_uuid: &str) -> bool {
+    let mut runs = get_all_interrupted_runs();
+    if runs.remove(job_uuid).is_some() {
+        return set_all_interrupted_runs(runs);
+    }
+    true
+}
+
+pub fn start_journal(run_dir: &Path, job_uuid: &str, files: &[String]) -> bool {
+    let journal = RunJournal {
+        job_uuid: job_uuid.to_string(),
+        output_dir: run_dir.to_string_lossy().to_string(),
+        files: files.to_vec(),
+        created: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0),
+    };
+
+    // A reused run folder may have been encrypted differently before
+    JOURNAL_KEYS.lock().unwrap().remove(run_dir);
+    let result = match get_journal_key(run_dir) {
+        Ok(Some(key)) => encryption_manager::encrypt_bytes_to_file(
+            &get_journal_path(run_dir),
+            to_json_string(&journal).as_bytes(),
+            &key,
+        ),
+        Ok(None) => write_json_file(get_journal_path(run_dir).to_string_lossy().to_string(), &journal),
+        Err(e) => Err(std::io::Error::other(e)),
+    };
+    if let Err(e) = result {
+        println!("Failed to write run journal: {}", e);
+        return false;
+    }
+    if let Err(e) = std::fs::write(get_journal_log_path(run_dir), "") {
+        println!("Failed to create run journal log: {}", e);
+        return false;
+    }
+
+    let mut runs = get_all_interrupted_runs();
+    runs.insert(job_uuid.to_string(), journal.output_dir);
+    set_all_interrupted_runs(runs)
+}
+
+fn append_journal_line(run_dir: &Path, line: String) {
+    let line = match get_journal_key(run_dir) {
+        Ok(Some(key)) => match encryption_manager::encrypt_line(&line, &key) {
+            Ok(encrypted_line) => encrypted_line,
+            Err(e) => {
+                println!("Failed to encrypt run journal line: {}", e);
+                return;
+            }
+        },
+        Ok(None) => line,
+        Err(e) => {
+            println!("Failed to write to run journal: {}", e);
+            return;
+        }
+    };
+    let _guard = JOURNAL_LOCK.lock().unwrap();
+    let file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(get_journal_log_path(run_dir));
+
+    match file {
+        Ok(mut file) => {
+            if let Err(e) = writeln!(file, "{}", line) {
+                println!("Failed to write to run journal: {}", e);
+            }
+        }
+        Err(e) => println!("Failed to open run journal: {}", e),
+    }
+}
+
+pub fn record_copied(run_dir: &Path, file: &str, hash: &str) {
+    append_journal_line(run_dir, format!("copied\t{}\t{}", hash, file));
+}
+
+fn is_hash(text: &str) -> bool {
+    text.len() == 64 && text.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+pub fn record_verified(run_dir: &Path, file: &str, hash: &str) {
+    append_journal_line(run_dir, format!("verified\t{}\t{}", hash, file));
+}
+
+pub fn record_failed(run_dir: &Path, file: &str) {
+    append_journal_line(run_dir, format!("failed\t{}", file));
+}
+
+fn read_journal_file(run_dir: &Path, key: Option<&EncryptionKey>) -> Result<RunJournal, String> {
+    let journal_path = get_journal_path(run_dir);
+    match key {
+        Some(key) => {
+            let data = encryption_manager::decrypt_to_bytes(&journal_path, key).map_err(|e| e.to_string())?;
+            from_json_string::<RunJournal>(String::from_utf8_lossy(&data).to_string()).map_err(|e| e.to_string())
+        }
+        None => read_json_file::<RunJournal>(journal_path.to_string_lossy().to_string()).map_err(|e| e.to_string()),
+    }
+}
+
+pub fn read_journal(run_dir: &Path) -> Option<JournalProgress> {
+    let key = match get_journal_key(run_dir) {
+        Ok(key) => key,
+        Err(e) => {
+            println!("Failed to read run journal: {}", e);
+            return None;
+        }
+    };
+    let journal = match read_journal_file(run_dir, key.as_ref()) {
+        Ok(journal) => journal,
+        Err(e) => {
+            println!("Failed to read run journal: {}", e);
+            return None;
+        }
+    };
+
+    let mut copied: HashSet<String> = HashSet::new();
+    let mut copied_hashes: HashMap<String, String> = HashMap::new();
+    let mut verified: HashMap<String, String> = HashMap::new();
+    let log = std::fs::read_to_string(get_journal_log_path(run_dir)).unwrap_or_default();
+
+    for line in log.lines() {
+        let line = match key.as_ref() {
+            Some(key) => match encryption_manager::decrypt_line(line, key) {
+                Some(line) => line,
+                None => continue,
+            },
+            None => line.to_string(),
+        };
+        let mut parts = line.splitn(2, '\t');
+        match (parts.next(), parts.next()) {
+            (Some("copied"), Some(rest)) => {
+                if let Some((hash, file)) = rest.split_once('\t').filter(|(hash, _)| is_hash(hash)) {
+                    copied.insert(file.to_string());
+                    copied_hashes.insert(file.to_string(), hash.to_string());
+                }
+            }
+            (Some("verified"), Some(rest)) => {
+                if let Some((hash, file)) = rest.split_once('\t').filter(|(hash, _)| is_hash(hash)) {
+                    copied.insert(file.to_string());
+                    verified.insert(file.to_string(), hash.to_string());
+                }
+            }
+            (Some("failed"), Some(file)) => {
+                copied.remove(file);
+                copied_hashes.remove(file);
+                verified.remove(file);
+            }
+            // A partially written last line is left over from a crash
+            _ => {}
+        }
+    }
+
+    Some(JournalProgress {
+        journal,
+        copied,
+        copied_hashes,
+        verified,
+    })
+}
+
+// Removes the journal of a completed run
+pub fn finish_journal(run_dir: &Path, job_uuid: &str) {
+    std::fs::remove_file(get_journal_path(run_dir)).unwrap_or_else(|e| {
+        println!("Failed to remove run journal: {}", e);
+    });
+    std::fs::remove_file(get_journal_log_path(run_dir)).unwrap_or_else(|e| {
+        println!("Failed to remove run journal log: {}", e);
+    });
+    JOURNAL_KEYS.lock().unwrap().remove(run_dir);
+    clear_interrupted_run(job_uuid);
+}
@@ -1,11 +1,24 @@
+mod archive_manager;
 mod background_manager;
+mod copy_manager;
+mod dedup_manager;
 mod drive_manager;
+mod encryption_manager;
+mod filter_manager;
 mod job_manager;
+mod journal_manager;
+mod link_manager;
 mod log_manager;
+mod manifest_manager;
+mod metadata_manager;
 mod settings_manager;
+mod snapshot_manager;
 mod storage_manager;
 mod structs;
+mod watch_manager;
 mod recovery_manager;
+mod schedule_manager;
+mod scrub_manager;
 
 use serde_json;
 use std::collections::HashMap;
@@ -98,8 +111,37 @@ fn get_recovery_logs() -> Vec<String> {
 }
 
 #[tauri::command]
-fn run_recovery(file_path: String, recovery_mode: String) -> bool {
-    recovery_manager::run_recovery(&file_path, &recovery_mode)
+fn run_recovery(file_path: String, recovery_mode: String, options: Option<structs::RecoveryOptions>) -> bool {
+    recovery_manager::run_recovery(&file_path, &recovery_mode, options)
+}
+
+#[tauri::command]
+fn preview_recovery(file_path: String, options: Option<structs::RecoveryOptions>) -> String {
+    match recovery_manager::preview_recovery(&file_path, options) {
+        Ok(preview) => serde_json::to_string(&preview).unwrap_or_else(|err| {
+            println!("Error serializing recovery preview to JSON: {}", err);
+            String::new()
+        }),
+        Err(e) => format!("Error: {}", e),
+    }
+}
+
+#[tauri::command]
+fn get_job_snapshots(uuid: String) -> String {
+    let job = storage_manager::get_job_by_uuid(&uuid);
+    let snapshots = snapshot_manager::get_job_snapshots(&job);
+    serde_json::to_string(&snapshots).unwrap_or_else(|err| {
+        println!("Error serializing snapshots to JSON: {}", err);
+        String::new()
+    })
+}
+
+#[tauri::command]
+fn get_next_scheduled_run(expression: String) -> String {
+    match schedule_manager::get_next_fire_time(&expression) {
+        Ok(next_run) => next_run.to_string(),
+        Err(e) => format!("Error: {}", e),
+    }
 }
 
 fn get_job_from_string(job_info: &str) -> Result<structs::JobInfo, serde_json::Error> {
@@ -117,6 +159,60 @@ fn start_job(uuid: String) -> bool {
     }
 }
 
+#[tauri::command]
+fn resume_job(uuid: String) -> bool {
+    if job_manager::resume_job(uuid) {
+        println!("Job resumed successfully.");
+        return true;
+    } else {
+        println!("Failed to resume job.");
+        return false;
+    }
+}
+
+#[tauri::command]
+fn get_resumable_run(uuid: String) -> String {
+    match job_manager::get_resumable_run(&uuid) {
+        Some(run) => serde_json::to_string(&run).unwrap_or_else(|err| {
+            println!("Error serializing resumable run to JSON: {}", err);
+            String::new()
+        }),
+        None => String::new(),
+    }
+}
+
+// Runs on a blocking thread since indexing large input folders can take a while
+#[tauri::command]
+async fn dry_run_job(uuid: String) -> String {
+    let report = match tauri::async_runtime::spawn_blocking(move || job_manager::dry_run_job(&uuid)).await {
+        Ok(report) => report,
+        Err(err) => {
+            println!("Error running dry run: {}", err);
+            return String::new();
+        }
+    };
+    serde_json::to_string(&report).unwrap_or_else(|err| {
+        println!("Error serializing dry run report to JSON: {}", err);
+        String::new()
+    })
+}
+
+// Runs on a blocking thread since re-reading every backed up file can take a while
+#[tauri::command]
+async fn scrub_job(uuid: String) -> String {
+    let report = match tauri::async_runtime::spawn_blocking(move || scrub_manager::scrub_job(&uuid)).await {
+        Ok(report) => report,
+        Err(err) => {
+            println!("Error running scrub: {}", err);
+            return String::new();
+        }
+    };
+    serde_json::to_string(&report).unwrap_or_else(|err| {
+        println!("Error serializing scrub report to JSON: {}", err);
+        String::new()
+    })
+}
+
 #[tauri::command]
 fn get_all_job_statuses() -> String {
     let statuses = job_manager::get_all_job_statuses();
@@ -136,6 +232,19 @@ fn setup_job(job_info: String) -> bool {
         }
     };
 
+    // Encrypted runs are written file by file, so archive output formats cannot be encrypted
+    if new_job.encryption.clone().unwrap_or("off".to_string()) != "off"
+        && archive_manager::get_output_format(&new_job) != "mirror"
+    {
+        println!("Encrypted jobs can only use the mirror output format.");
+        return false;
+    }
+
+    // Keys are only saved on this computer while the job asks for it
+    if !new_job.remember_key.unwrap_or(false) && !encryption_manager::forget_saved_keys(&new_job.uuid) {
+        println!("Failed to remove the saved keys of job {}", new_job.uuid);
+    }
+
     // Assign job to drive
     if new_job.output_device == "special:thisdrive" {
         // Determine drive from output folder
@@ -147,6 +256,10 @@ fn setup_job(job_info: String) -> bool {
             }
         };
         println!("Root drive is {}", root_drive);
+        if drive_manager::is_fallback_drive(&new_job.output_dir, &root_drive) {
+            println!("The output drive is not connected.");
+            return false;
+        }
 
         // Determine or create drive UUID
         let drive_uuid = drive_manager::get_drive_uuid(&root_drive);
@@ -168,6 +281,10 @@ fn setup_job(job_info: String) -> bool {
                 return false;
             }
         };
+        if drive_manager::is_fallback_drive(&new_job.output_dir, &root_drive) {
+            println!("The output drive is not connected.");
+            return false;
+        }
         drive_manager::add_job_to_drive(&root_drive, new_job.clone());
     }
 
@@ -328,6 +445,27 @@ fn clear_recovery_status(){
     recovery_manager::clear_recovery_status();
 }
 
+#[tauri::command]
+fn create_encryption_salt() -> String {
+    encryption_manager::create_salt()
+}
+
+#[tauri::command]
+fn set_job_passphrase(uuid: String, passphrase: String, output_dir: String, remember_key: bool) -> String {
+    match encryption_manager::set_job_passphrase(&uuid, &passphrase, &output_dir, remember_key) {
+        Ok(salt) => salt,
+        Err(e) => format!("Error: {}", e),
+    }
+}
+
+#[tauri::command]
+fn unlock_backup(file_path: String, passphrase: Option<String>, keyfile: Option<String>) -> String {
+    match recovery_manager::unlock_backup(&file_path, passphrase, keyfile) {
+        Ok(()) => "Success".to_string(),
+        Err(e) => format!("Error: {}", e),
+    }
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
@@ -351,6 +489,10 @@ pub fn run() {
             remove_job_by_uuid,
             get_all_job_statuses,
             start_job,
+            resume_job,
+            get_resumable_run,
+            dry_run_job,
+            scrub_job,
             clear_completed_jobs,
             pause_job,
             unpause_job,
@@ -369,7 +511,13 @@ pub fn run() {
             get_recovery_progress,
             get_recovery_logs,
             run_recovery,
-            clear_recovery_status
+            preview_recovery,
+            get_job_snapshots,
+            get_next_scheduled_run,
+            clear_recovery_status,
+            create_encryption_salt,
+            set_job_passphrase,
+            unlock_backup
         ])
         .setup(|app| {
             // Store the app handle in a global variable for later use
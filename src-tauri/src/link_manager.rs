@@ -0,0 +1,217 @@
+use crate::structs::JobInfo;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/*
+How jobs treat links and special files while indexing and copying.
+The job's symlink_policy decides what happens to symbolic links:
+- "skip" leaves them out (Default)
+- "follow" backs up what they point to. Folders are identified by device and inode (or by their canonical
+  path on systems without inodes), so a link back to a folder that was already walked is not entered again
+- "link" backs up the link itself. Its target is recorded in the manifest, the link is recreated in
+  mirrored runs, and recovery recreates it from the manifest
+Sockets, FIFOs and device nodes are always skipped, since they have no contents to copy.
+Files with several hard links in a run are linked together in mirrored output too (the job's preserve_hardlinks).
+ */
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum FileId {
+    Inode(u64, u64),
+    Path(PathBuf),
+}
+
+#[derive(PartialEq)]
+pub enum EntryKind {
+    File,
+    Dir,
+    Skipped,
+}
+
+// Gets how a job treats symbolic links
+pub fn get_symlink_policy(job_info: &JobInfo) -> String {
+    match job_info.symlink_policy.clone().unwrap_or_default().as_str() {
+        "follow" => "follow".to_string(),
+        "link" => "link".to_string(),
+        _ => "skip".to_string(),
+    }
+}
+
+fn get_file_id(path: &Path, metadata: &fs::Metadata) -> Option<FileId> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let _ = path;
+        Some(FileId::Inode(metadata.dev(), metadata.ino()))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        fs::canonicalize(path).ok().map(FileId::Path)
+    }
+}
+
+// Gets the id shared by every hard link to a file, for files that have more than one
+pub fn get_hardlink_id(path: &str) -> Option<FileId> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let metadata = fs::metadata(path).ok()?;
+        if metadata.nlink() > 1 {
+            return Some(FileId::Inode(metadata.dev(), metadata.ino()));
+        }
+        None
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+// Gets the target of a symbolic link, or None if the path is not one
+pub fn read_link_target(path: &str) -> Option<String> {
+    let metadata = fs::symlink_metadata(path).ok()?;
+    if !metadata.file_type().is_symlink() {
+        return None;
+    }
+    fs::read_link(path)
+        .ok()
+        .map(|target| target.to_string_lossy().to_string())
+}
+
+// Creates a symbolic link, replacing whatever is at its path
+pub fn create_symlink(target: &str, link: &Path) -> std::io::Result<()> {
+    if fs::symlink_metadata(link).is_ok() {
+        fs::remove_file(link)?;
+    }
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(target, link)
+    }
+    #[cfg(windows)]
+    {
+        // Relative targets are resolved from the folder holding the link
+        let resolved = link.parent().map_or(PathBuf::from(target), |parent| parent.join(target));
+        if resolved.is_dir() {
+            std::os::windows::fs::symlink_dir(target, link)
+        } else {
+            std::os::windows::fs::symlink_file(target, link)
+        }
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = target;
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "symbolic links are not supported"))
+    }
+}
+
+// Links a file to one already written in the same run, or copies it where hard links are not supported.
+// Returns whether the file was linked.
+pub fn link_or_copy(existing: &Path, path: &Path) -> std::io::Result<bool> {
+    if fs::symlink_metadata(path).is_ok() {
+        fs::remove_file(path)?;
+    }
+    if fs::hard_link(existing, path).is_ok() {
+        return Ok(true);
+    }
+    fs::copy(existing, path)?;
+    Ok(false)
+}
+
+fn get_special_file_name(file_type: &fs::FileType) -> &'static str {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        if file_type.is_socket() {
+            return "socket";
+        }
+        if file_type.is_fifo() {
+            return "FIFO";
+        }
+        if file_type.is_block_device() || file_type.is_char_device() {
+            return "device node";
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = file_type;
+    "special file"
+}
+
+// The state of a walk over a job's input folders
+pub struct FileWalk {
+    pub symlink_policy: String,
+    visited_dirs: HashSet<FileId>,
+    // Everything left out of the walk, with the reason
+    pub skipped: Vec<String>,
+}
+
+impl FileWalk {
+    pub fn new(symlink_policy: &str) -> FileWalk {
+        FileWalk {
+            symlink_policy: symlink_policy.to_string(),
+            visited_dirs: HashSet::new(),
+            skipped: Vec::new(),
+        }
+    }
+
+    pub fn skip(&mut self, path: &Path, reason: &str) {
+        let message = format!("Skipped {}: {}", path.display(), reason);
+        println!("{}", message);
+        self.skipped.push(message);
+    }
+
+    // Records a folder as walked. Returns false if it already was, such as when a link leads back to it.
+    pub fn enter_dir(&mut self, path: &Path) -> bool {
+        match fs::metadata(path).ok().and_then(|metadata| get_file_id(path, &metadata)) {
+            Some(id) => self.visited_dirs.insert(id),
+            None => true,
+        }
+    }
+
+    // Works out whether a folder entry is backed up as a file, walked as a folder or skipped
+    pub fn get_entry_kind(&mut self, entry: &fs::DirEntry) -> EntryKind {
+        let path = entry.path();
+        let file_type = match entry.file_type() {
+            Ok(file_type) => file_type,
+            Err(e) => {
+                self.skip(&path, &e.to_string());
+                return EntryKind::Skipped;
+            }
+        };
+
+        if file_type.is_symlink() {
+            match self.symlink_policy.as_str() {
+                "link" => return EntryKind::File,
+                "follow" => {
+                    return match fs::metadata(&path) {
+                        Ok(metadata) if metadata.is_dir() => EntryKind::Dir,
+                        Ok(metadata) if metadata.is_file() => EntryKind::File,
+                        Ok(metadata) => {
+                            let name = get_special_file_name(&metadata.file_type());
+                            self.skip(&path, &format!("the link points to a {}", name));
+                            EntryKind::Skipped
+                        }
+                        Err(_) => {
+                            self.skip(&path, "the link's target does not exist");
+                            EntryKind::Skipped
+                        }
+                    };
+                }
+                _ => {
+                    self.skip(&path, "symbolic link");
+                    return EntryKind::Skipped;
+                }
+            }
+        }
+
+        if file_type.is_file() {
+            EntryKind::File
+        } else if file_type.is_dir() {
+            EntryKind::Dir
+        } else {
+            self.skip(&path, get_special_file_name(&file_type));
+            EntryKind::Skipped
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use crate::{archive_manager, dedup_manager, encryption_manager, storage_manager};
+use sha2::{Digest, Sha256};
+use crate::structs::{ManifestEntry, RunManifest};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+// Gets the size in bytes and modification time (seconds since UNIX_EPOCH) of a file
+pub fn get_file_fingerprint(path: &str) -> std::io::Result<(u64, u64)> {
+    let metadata = fs::metadata(path)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    Ok((metadata.len(), modified))
+}
+
+pub fn hash_to_hex(hash: &[u8]) -> String {
+    hash.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn hex_to_bytes(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+pub fn get_manifest_path(run_dir: &Path) -> PathBuf {
+    run_dir.join("manifest.json")
+}
+
+pub fn read_manifest(run_dir: &Path) -> Option<RunManifest> {
+    let manifest_path = get_manifest_path(run_dir);
+    if !manifest_path.exists() {
+        return None;
+    }
+
+    // Manifests of encrypted runs are encrypted with the run's key
+    if encryption_manager::is_encrypted(&manifest_path) {
+        let key = match encryption_manager::get_run_key(run_dir) {
+            Ok(Some(key)) => key,
+            Ok(None) => return None,
+            Err(e) => {
+                println!("Failed to read manifest {}: {}", manifest_path.display(), e);
+                return None;
+            }
+        };
+        return match encryption_manager::decrypt_to_bytes(&manifest_path, &key)
+            .map_err(|e| e.to_string())
+            .and_then(|data| {
+                storage_manager::from_json_string::<RunManifest>(String::from_utf8_lossy(&data).to_string())
+                    .map_err(|e| e.to_string())
+            }) {
+            Ok(manifest) => Some(manifest),
+            Err(e) => {
+                println!("Failed to read manifest {}: {}", manifest_path.display(), e);
+                None
+            }
+        };
+    }
+
+    match storage_manager::read_json_file::<RunManifest>(
+        manifest_path.to_string_lossy().to_string(),
+    ) {
+        Ok(manifest) => Some(manifest),
+        Err(e) => {
+            println!(
+                "Failed to read manifest {}: {}",
+                manifest_path.display(),
+                e
+            );
+            None
+        }
+    }
+}
+
+pub fn write_manifest(run_dir: &Path, manifest: &RunManifest) -> bool {
+    let manifest_path = get_manifest_path(run_dir);
+    let result = match encryption_manager::get_encrypted_run_key(run_dir) {
+        Ok(Some(key)) => encryption_manager::encrypt_bytes_to_file(
+            &manifest_path,
+            storage_manager::to_json_string(manifest).as_bytes(),
+            &key,
+        ),
+        Ok(None) => storage_manager::write_json_file(manifest_path.to_string_lossy().to_string(), manifest),
+        Err(e) => Err(std::io::Error::other(e)),
+    };
+    match result {
+        Ok(_) => true,
+        Err(e) => {
+            println!(
+                "Failed to write manifest {}: {}",
+                manifest_path.display(),
+                e
+            );
+            false
+        }
+    }
+}
+
+pub fn get_checksums_path(run_dir: &Path) -> PathBuf {
+    run_dir.join("checksums.sha256")
+}
+
+// Gets the path of a run's output file relative to the run folder, with '/' separators.
+// Blobs in a deduplicated store are outside the snapshot folder, so their paths start with "../../".
+fn get_checksum_file_path(run_dir: &Path, output: &Path) -> String {
+    let relative = match output.strip_prefix(run_dir) {
+        Ok(relative) => relative.to_path_buf(),
+        Err(_) => match dedup_manager::get_store_dir_from_snapshot(run_dir)
+            .and_then(|store_dir| output.strip_prefix(&store_dir).ok().map(|relative| relative.to_path_buf()))
+        {
+            Some(relative) => Path::new("../..").join(relative),
+            None => output.to_path_buf(),
+        },
+    };
+    relative.to_string_lossy().replace('\\', "/")
+}
+
+// Writes the SHA-256 hash of every output file of a run to checksums.sha256 in the run folder.
+// It uses the sha256sum format ("<hash>  <path>"), so a run can be checked without the source files,
+// by Archway or with `sha256sum -c checksums.sha256` from inside the run folder.
+pub fn write_checksums(run_dir: &Path, entries: &[ManifestEntry], output_files: &[String]) -> bool {
+    let mut checksums = String::new();
+    let encrypted = encryption_manager::is_run_encrypted(run_dir);
+    let mut volumes: Vec<PathBuf> = Vec::new();
+    for (entry, output_file) in entries.iter().zip(output_files.iter()) {
+        if entry.link_target.is_some() {
+            continue;
+        }
+        // Files in archives are checked through the archive volume holding them
+        if let Some((volume, _)) = archive_manager::split_archive_path(Path::new(output_file)) {
+            if !volumes.contains(&volume) {
+                volumes.push(volume);
+            }
+            continue;
+        }
+        // Encrypted runs only list hashes of the files as stored, since plaintext hashes reveal their contents
+        let hash = if encrypted {
+            entry.stored_hash.as_ref()
+        } else {
+            entry.stored_hash.as_ref().or(entry.hash.as_ref())
+        };
+        match hash {
+            Some(hash) => checksums.push_str(&format!(
+                "{}  {}\n",
+                hash,
+                get_checksum_file_path(run_dir, Path::new(output_file))
+            )),
+            None => println!("No checksum recorded for {}", output_file),
+        }
+    }
+    for volume in volumes {
+        let hash = fs::File::open(&volume).and_then(|mut file| {
+            let mut hasher = Sha256::new();
+            std::io::copy(&mut file, &mut hasher)?;
+            Ok(hash_to_hex(&hasher.finalize()))
+        });
+        match hash {
+            Ok(hash) => checksums.push_str(&format!(
+                "{}  {}\n",
+                hash,
+                get_checksum_file_path(run_dir, &volume)
+            )),
+            Err(e) => println!("Failed to hash volume {}: {}", volume.display(), e),
+        }
+    }
+
+    match fs::write(get_checksums_path(run_dir), checksums) {
+        Ok(_) => true,
+        Err(e) => {
+            println!("Failed to write checksums for {}: {}", run_dir.display(), e);
+            false
+        }
+    }
+}
+
+// Gets the folder holding the previous run of a job, given the folder of the current run.
+// Jobs keeping a single copy reuse the same folder, while jobs keeping multiple copies
+// write to archway-<uuid>-N, so the previous run is archway-<uuid>-(N-1).
+// Deduplicated stores use the newest completed snapshot before this one.
+pub fn get_previous_run_dir(run_dir: &Path, job_uuid: &str) -> Option<PathBuf> {
+    // Deduplicated stores keep runs as snapshots instead of numbered folders
+    if dedup_manager::get_store_dir_from_snapshot(run_dir).is_some() {
+        return dedup_manager::get_previous_snapshot_dir(run_dir);
+    }
+
+    let folder_name = run_dir.file_name()?.to_string_lossy().to_string();
+    let base_name = format!("archway-{}", job_uuid);
+
+    if folder_name == base_name {
+        return Some(run_dir.to_path_buf());
+    }
+
+    let copy_number = folder_name
+        .strip_prefix(&(base_name.clone() + "-"))?
+        .parse::<u32>()
+        .ok()?;
+    if copy_number <= 1 {
+        return None;
+    }
+
+    let previous_dir = run_dir.with_file_name(format!("{}-{}", base_name, copy_number - 1));
+    if previous_dir.exists() {
+        Some(previous_dir)
+    } else {
+        None
+    }
+}
+
+// Places the unchanged output of a previous run at the new output path.
+// If the previous run wrote to the same path, the existing file is kept as is.
+// Otherwise a hard link is created, falling back to a copy on file systems without hard links.
+pub fn reuse_previous_output(
+    previous_entry: &ManifestEntry,
+    previous_run_dir: &Path,
+    new_output: &Path,
+) -> bool {
+    let previous_output = previous_run_dir.join(&previous_entry.output);
+
+    match fs::metadata(&previous_output) {
+        Ok(metadata) => {
+            if metadata.len() != previous_entry.size {
+                println!(
+                    "Previous output does not match manifest: {}",
+                    previous_output.display()
+                );
+                return false;
+            }
+        }
+        Err(_) => {
+            println!("Previous output does not exist: {}", previous_output.display());
+            return false;
+        }
+    }
+
+    if previous_output == new_output {
+        return true;
+    }
+
+    if fs::hard_link(&previous_output, new_output).is_ok() {
+        return true;
+    }
+
+    match fs::copy(&previous_output, new_output) {
+        Ok(_) => true,
+        Err(e) => {
+            println!(
+                "Failed to reuse previous output {}: {}",
+                previous_output.display(),
+                e
+            );
+            false
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use crate::structs::{FileMetadata, FileTimestamp, JobInfo};
+use filetime::FileTime;
+use std::fs;
+use std::path::Path;
+
+/*
+Backed up files keep the modification and access times and permission bits of their source, and optionally
+the owner and extended attributes (the job's preserve_ownership and preserve_xattrs, Unix only).
+The metadata is also recorded in each run's manifest, because files in deduplicated stores and archives
+are shared or have no metadata of their own, and recovery restores it from there.
+ */
+
+fn to_timestamp(time: FileTime) -> FileTimestamp {
+    FileTimestamp {
+        seconds: time.unix_seconds(),
+        nanos: time.nanoseconds(),
+    }
+}
+
+fn to_file_time(timestamp: &FileTimestamp) -> FileTime {
+    FileTime::from_unix_time(timestamp.seconds, timestamp.nanos)
+}
+
+// Gets the time a file was last modified, as recorded in its metadata
+pub fn get_modified_time(metadata: &FileMetadata) -> Option<std::time::SystemTime> {
+    let modified = metadata.modified.as_ref()?;
+    let seconds = u64::try_from(modified.seconds).ok()?;
+    Some(std::time::UNIX_EPOCH + std::time::Duration::new(seconds, modified.nanos))
+}
+
+#[cfg(unix)]
+fn read_xattrs(path: &str) -> std::collections::HashMap<String, String> {
+    let mut xattrs = std::collections::HashMap::new();
+    match xattr::list(path) {
+        Ok(names) => {
+            for name in names {
+                if let Ok(Some(value)) = xattr::get(path, &name) {
+                    xattrs.insert(name.to_string_lossy().to_string(), crate::manifest_manager::hash_to_hex(&value));
+                }
+            }
+        }
+        Err(e) => println!("Failed to list extended attributes of {}: {}", path, e),
+    }
+    xattrs
+}
+
+// Reads the metadata of a file to back up along with it
+pub fn read_metadata(path: &str, job_info: &JobInfo) -> Option<FileMetadata> {
+    let metadata = fs::metadata(path).ok()?;
+    #[allow(unused_mut)]
+    let mut file_metadata = FileMetadata {
+        modified: Some(to_timestamp(FileTime::from_last_modification_time(&metadata))),
+        accessed: Some(to_timestamp(FileTime::from_last_access_time(&metadata))),
+        readonly: metadata.permissions().readonly(),
+        mode: None,
+        uid: None,
+        gid: None,
+        xattrs: None,
+    };
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        file_metadata.mode = Some(metadata.mode() & 0o7777);
+        if job_info.preserve_ownership.unwrap_or(false) {
+            file_metadata.uid = Some(metadata.uid());
+            file_metadata.gid = Some(metadata.gid());
+        }
+        if job_info.preserve_xattrs.unwrap_or(false) {
+            file_metadata.xattrs = Some(read_xattrs(path));
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = job_info;
+
+    Some(file_metadata)
+}
+
+#[cfg(unix)]
+fn apply_ownership_and_xattrs(path: &Path, metadata: &FileMetadata, warnings: &mut Vec<String>) {
+    if let (Some(uid), Some(gid)) = (metadata.uid, metadata.gid) {
+        if let Err(e) = std::os::unix::fs::chown(path, Some(uid), Some(gid)) {
+            warnings.push(format!("Could not set the owner of {}: {}", path.display(), e));
+        }
+    }
+    for (name, value) in metadata.xattrs.iter().flatten() {
+        let result = match crate::manifest_manager::hex_to_bytes(value) {
+            Some(value) => xattr::set(path, name, &value),
+            None => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid value")),
+        };
+        if let Err(e) = result {
+            warnings.push(format!(
+                "Could not set extended attribute {} of {}: {}",
+                name,
+                path.display(),
+                e
+            ));
+        }
+    }
+}
+
+fn apply_permissions(path: &Path, metadata: &FileMetadata) -> std::io::Result<()> {
+    #[cfg(unix)]
+    if let Some(mode) = metadata.mode {
+        use std::os::unix::fs::PermissionsExt;
+        return fs::set_permissions(path, fs::Permissions::from_mode(mode));
+    }
+
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_readonly(metadata.readonly);
+    fs::set_permissions(path, permissions)
+}
+
+// Applies recorded metadata to a copied or restored file.
+// Returns a warning for every part that could not be applied, since the file itself is intact.
+pub fn apply_metadata(path: &Path, metadata: &FileMetadata) -> Vec<String> {
+    let mut warnings: Vec<String> = Vec::new();
+
+    // The owner is set before the permissions, since changing it clears bits such as setuid
+    #[cfg(unix)]
+    apply_ownership_and_xattrs(path, metadata, &mut warnings);
+
+    // Times are set before the permissions, since read-only files cannot have their times changed on Windows
+    if let (Some(accessed), Some(modified)) = (metadata.accessed.as_ref(), metadata.modified.as_ref()) {
+        if let Err(e) = filetime::set_file_times(path, to_file_time(accessed), to_file_time(modified)) {
+            warnings.push(format!("Could not set the times of {}: {}", path.display(), e));
+        }
+    }
+
+    if let Err(e) = apply_permissions(path, metadata) {
+        warnings.push(format!("Could not set the permissions of {}: {}", path.display(), e));
+    }
+
+    warnings
+}
@@ -1,47 +1,79 @@
 use std::sync::LazyLock;
 use std::sync::Mutex;
 use tauri_plugin_dialog::FilePath;
-use crate::{job_manager, storage_manager};
+use crate::encryption_manager::EncryptionKey;
+use crate::{archive_manager, encryption_manager, job_manager, link_manager, manifest_manager, metadata_manager, snapshot_manager, storage_manager};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
 use crate::storage_manager::to_json_string;
+use crate::structs::{FileMetadata, ManifestEntry, RecoveryOptions, RecoveryPreview};
+use globset::{Glob, GlobSet, GlobSetBuilder};
 
 static RECOVERY_PROGRESS: LazyLock<Mutex<f32>> = LazyLock::new(|| Mutex::new(-1.0));
 static RECOVERY_LOGS: LazyLock<Mutex<Vec<String>>> = LazyLock::new(|| Mutex::new(vec![]));
 
-pub fn verify_recovery_file(file_path: &str) -> &str {
+// Reads a recovery file, decrypting it first if its backup is encrypted
+fn read_recovery_data(file_path: &str) -> Result<Vec<Vec<String>>, String> {
+    let recovery_file = Path::new(file_path);
+    if !encryption_manager::is_encrypted(recovery_file) {
+        return storage_manager::read_json_file::<Vec<Vec<String>>>(file_path.to_string()).map_err(|e| {
+            println!("Error reading recovery file: {}", e);
+            "Error: Recovery file is not JSON or not in the correct format.".to_string()
+        });
+    }
+
+    let run_dir = recovery_file.parent().unwrap_or(Path::new(""));
+    let key = match encryption_manager::get_run_key(run_dir) {
+        Ok(Some(key)) => key,
+        Ok(None) => return Err("Error: This backup is encrypted, but no key was found for it.".to_string()),
+        Err(e) => return Err(format!("Error: {}", e)),
+    };
+    let data = encryption_manager::decrypt_to_bytes(recovery_file, &key).map_err(|e| {
+        println!("Error decrypting recovery file: {}", e);
+        "Error: This backup is encrypted and could not be decrypted with the saved key.".to_string()
+    })?;
+    storage_manager::from_json_string(String::from_utf8_lossy(&data).to_string()).map_err(|e| {
+        println!("Error reading recovery file: {}", e);
+        "Error: Recovery file is not JSON or not in the correct format.".to_string()
+    })
+}
+
+pub fn verify_recovery_file(file_path: &str) -> String {
     let file_path = file_path.replace("\\", "/");
 
     println!("Verifying recovery file at path: {}", file_path);
     // Replace all backslashes with forward slashes for consistency
-    return match storage_manager::read_json_file::<Vec<Vec<String>>>(
-        file_path.to_string()
-    ) {
+    return match read_recovery_data(&file_path) {
         Ok(recovery_data) => {
             // Check that all inner arrays have exactly two string elements
             for entry in recovery_data.iter() {
                 if entry.len() != 2 {
-                    return "Error: Each entry in the recovery file must contain exactly two string elements.";
+                    return "Error: Each entry in the recovery file must contain exactly two string elements.".to_string();
                 }
             }
 
-            "Valid"
+            "Valid".to_string()
         }
-        Err(e) => {
-            println!("Error reading recovery file: {}", e);
-            "Error: Recovery file is not JSON or not in the correct format."
-        },
+        Err(e) => e,
     }
 }
 
+// Saves the key of an encrypted backup from its passphrase or keyfile, so it can be restored
+pub fn unlock_backup(file_path: &str, passphrase: Option<String>, keyfile: Option<String>) -> Result<(), String> {
+    let file_path = file_path.replace("\\", "/");
+    let run_dir = Path::new(&file_path).parent().unwrap_or(Path::new(""));
+    encryption_manager::unlock_run(run_dir, passphrase, keyfile)
+}
+
 
 pub fn get_recovery_file(file_path: &str) -> String {
     let file_path = file_path.replace("\\", "/");
 
     println!("Verifying recovery file at path: {}", file_path);
     // Replace all backslashes with forward slashes for consistency
-    return match storage_manager::read_json_file::<Vec<Vec<String>>>(
-        file_path.to_string()
-    ) {
-        Ok(recovery_data) => {
+    return match read_recovery_data(&file_path) {
+        Ok(mut recovery_data) => {
             // Check that all inner arrays have exactly two string elements
             for entry in recovery_data.iter() {
                 if entry.len() != 2 {
@@ -49,12 +81,15 @@ pub fn get_recovery_file(file_path: &str) -> String {
                 }
             }
 
+            // Point backup paths at where the backup is now, in case copies were rotated or the drive moved
+            let recovery_file = std::path::Path::new(&file_path);
+            for entry in recovery_data.iter_mut() {
+                entry[0] = snapshot_manager::rebase_backup_path(&entry[0], recovery_file);
+            }
+
             to_json_string(&recovery_data)
         }
-        Err(e) => {
-            println!("Error reading recovery file: {}", e);
-            "Error: Recovery file is not JSON or not in the correct format.".to_string()
-        },
+        Err(e) => e,
     }
 }
 
@@ -65,7 +100,189 @@ fn get_last_modified_time(path: &std::path::Path) -> Option<std::time::SystemTim
     }
 }
 
-pub fn run_recovery(file_path: &str, recovery_mode: &str) -> bool {
+fn build_glob_set(patterns: &[String]) -> Result<GlobSet, String> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern).map_err(|e| format!("Invalid pattern '{}': {}", pattern, e))?;
+        builder.add(glob);
+    }
+    builder.build().map_err(|e| format!("Invalid patterns: {}", e))
+}
+
+// Keeps only the recovery entries selected by the recovery options
+// Patterns and the path prefix are matched against the original file path
+fn filter_recovery_data(recovery_data: Vec<Vec<String>>, options: &RecoveryOptions) -> Result<Vec<Vec<String>>, String> {
+    let include = match &options.include {
+        Some(patterns) if !patterns.is_empty() => Some(build_glob_set(patterns)?),
+        _ => None,
+    };
+    let exclude = match &options.exclude {
+        Some(patterns) if !patterns.is_empty() => Some(build_glob_set(patterns)?),
+        _ => None,
+    };
+    let path_prefix = options
+        .path_prefix
+        .as_ref()
+        .map(|prefix| prefix.replace("\\", "/"));
+
+    Ok(recovery_data
+        .into_iter()
+        .filter(|entry| {
+            let original_path = entry[1].replace("\\", "/");
+
+            if let Some(files) = &options.files {
+                if !files.contains(&entry[1]) {
+                    return false;
+                }
+            }
+            if let Some(prefix) = &path_prefix {
+                if !std::path::Path::new(&original_path).starts_with(prefix) {
+                    return false;
+                }
+            }
+            if let Some(include) = &include {
+                if !include.is_match(&original_path) {
+                    return false;
+                }
+            }
+            if let Some(exclude) = &exclude {
+                if exclude.is_match(&original_path) {
+                    return false;
+                }
+            }
+            true
+        })
+        .collect())
+}
+
+// Replaces the start of a path if it matches the given prefix, comparing folder by folder
+fn replace_path_prefix(path: &str, from: &str, to: &str) -> Option<String> {
+    let normalized_path = path.replace("\\", "/");
+    let normalized_from = from.replace("\\", "/");
+    let rest = normalized_path.strip_prefix(normalized_from.trim_end_matches('/'))?;
+    if !rest.is_empty() && !rest.starts_with('/') {
+        return None;
+    }
+
+    let mut new_path = PathBuf::from(to);
+    for component in rest.split('/').filter(|component| !component.is_empty()) {
+        new_path = new_path.join(component);
+    }
+    Some(new_path.to_string_lossy().to_string())
+}
+
+// Gets the home folder a path is in, such as /home/<user>, /Users/<user> or C:/Users/<user>
+fn get_home_prefix(path: &str) -> Option<String> {
+    let normalized = path.replace("\\", "/");
+    let components: Vec<&str> = normalized.split('/').collect();
+    let home_index = components
+        .iter()
+        .position(|component| *component == "home" || *component == "Users")?;
+    if home_index > 1 || components.len() <= home_index + 1 {
+        return None;
+    }
+    Some(components[..home_index + 2].join("/"))
+}
+
+// Rewrites the original paths of recovery entries to restore to a different location.
+// An explicit prefix rewrite is applied first, then library folders (documents, pictures and so on)
+// are moved to the current user's library folders.
+fn remap_recovery_targets(recovery_data: &mut [Vec<String>], recovery_file: &Path, options: &RecoveryOptions) {
+    let remap_libraries = options.remap_libraries.unwrap_or(false);
+
+    // The manifest records where each library was when the backup was made
+    let backup_libraries: HashMap<String, String> = match recovery_file.parent() {
+        Some(run_dir) if remap_libraries => manifest_manager::read_manifest(run_dir)
+            .and_then(|manifest| manifest.libraries)
+            .unwrap_or_default(),
+        _ => HashMap::new(),
+    };
+
+    for entry in recovery_data.iter_mut() {
+        if let (Some(from), Some(to)) = (&options.target_prefix_from, &options.target_prefix_to) {
+            if let Some(new_path) = replace_path_prefix(&entry[1], from, to) {
+                entry[1] = new_path;
+                continue;
+            }
+        }
+
+        if !remap_libraries {
+            continue;
+        }
+
+        let mut remapped = false;
+        for (library, backup_library_dir) in &backup_libraries {
+            if let Some(library_dir) = job_manager::get_library_dir(library) {
+                if let Some(new_path) = replace_path_prefix(
+                    &entry[1],
+                    backup_library_dir,
+                    &library_dir.to_string_lossy(),
+                ) {
+                    entry[1] = new_path;
+                    remapped = true;
+                    break;
+                }
+            }
+        }
+
+        // Backups without library information fall back to moving the old home folder to the current one
+        if !remapped && backup_libraries.is_empty() {
+            if let (Some(home_prefix), Some(home_dir)) = (get_home_prefix(&entry[1]), dirs::home_dir()) {
+                if let Some(new_path) = replace_path_prefix(&entry[1], &home_prefix, &home_dir.to_string_lossy()) {
+                    entry[1] = new_path;
+                }
+            }
+        }
+    }
+}
+
+// Gets the manifest entries of the files in a recovery file, keyed by where each file is backed up.
+// Manifests record the size, modification time and metadata of the source, which backed up files in
+// deduplicated stores and archives do not have on disk. Must be called before the targets are remapped.
+fn get_backed_up_files(recovery_data: &[Vec<String>], run_dir: &Path) -> HashMap<String, ManifestEntry> {
+    let mut manifest_entries: HashMap<String, ManifestEntry> = match manifest_manager::read_manifest(run_dir) {
+        Some(manifest) => manifest
+            .files
+            .into_iter()
+            .map(|entry| (entry.source.clone(), entry))
+            .collect(),
+        None => return HashMap::new(),
+    };
+
+    recovery_data
+        .iter()
+        .filter_map(|entry| Some((entry[0].clone(), manifest_entries.remove(&entry[1])?)))
+        .collect()
+}
+
+// Gets the recovery entries the recovery options select and their total size, without restoring anything
+pub fn preview_recovery(file_path: &str, options: Option<RecoveryOptions>) -> Result<RecoveryPreview, String> {
+    let recovery_data_json = get_recovery_file(file_path);
+    let mut recovery_data: Vec<Vec<String>> = storage_manager::from_json_string(recovery_data_json)
+        .map_err(|e| format!("Error parsing recovery data: {}", e))?;
+    let run_dir = Path::new(&file_path.replace("\\", "/")).parent().map(|run_dir| run_dir.to_path_buf()).unwrap_or_default();
+    let backed_up_files = get_backed_up_files(&recovery_data, &run_dir);
+
+    if let Some(options) = options {
+        recovery_data = filter_recovery_data(recovery_data, &options)?;
+        remap_recovery_targets(&mut recovery_data, Path::new(&file_path.replace("\\", "/")), &options);
+    }
+
+    let total_size = recovery_data
+        .iter()
+        .map(|entry| match backed_up_files.get(&entry[0]) {
+            Some(backed_up_file) => backed_up_file.size,
+            None => std::fs::metadata(&entry[0]).map_or(0, |metadata| metadata.len()),
+        })
+        .sum();
+
+    Ok(RecoveryPreview {
+        entries: recovery_data,
+        total_size,
+    })
+}
+
+pub fn run_recovery(file_path: &str, recovery_mode: &str, options: Option<RecoveryOptions>) -> bool {
     let recovery_data_json = get_recovery_file(file_path);
     let job_statuses = job_manager::get_all_job_statuses();
     if (!job_statuses.is_empty()) {
@@ -73,21 +290,132 @@ pub fn run_recovery(file_path: &str, recovery_mode: &str) -> bool {
         return false;
     }
 
-    let recovery_data: Vec<Vec<String>> = match storage_manager::from_json_string(recovery_data_json) {
+    let mut recovery_data: Vec<Vec<String>> = match storage_manager::from_json_string(recovery_data_json) {
         Ok(data) => data,
         Err(e) => {
             println!("Error parsing recovery data: {}", e);
             return false;
         }
     };
+    let run_dir = Path::new(&file_path.replace("\\", "/")).parent().map(|run_dir| run_dir.to_path_buf()).unwrap_or_default();
+    let backed_up_files = get_backed_up_files(&recovery_data, &run_dir);
 
-    tauri::async_runtime::spawn(recovery_worker(recovery_data, recovery_mode.to_string()));
+    if let Some(options) = options {
+        recovery_data = match filter_recovery_data(recovery_data, &options) {
+            Ok(data) => data,
+            Err(e) => {
+                println!("Error filtering recovery data: {}", e);
+                return false;
+            }
+        };
+        remap_recovery_targets(&mut recovery_data, Path::new(&file_path.replace("\\", "/")), &options);
+    }
+
+    // Encrypted backups are decrypted as they are restored
+    let file_path = file_path.replace("\\", "/");
+    let encryption_key = if encryption_manager::is_encrypted(Path::new(&file_path)) {
+        match encryption_manager::get_run_key(&run_dir) {
+            Ok(key) => key,
+            Err(e) => {
+                println!("Cannot run recovery: {}", e);
+                return false;
+            }
+        }
+    } else {
+        // Unencrypted runs of a deduplicated store can still point at blobs an encrypted run stored
+        encryption_manager::get_run_key(&run_dir).unwrap_or(None)
+    };
+
+    tauri::async_runtime::spawn(recovery_worker(recovery_data, recovery_mode.to_string(), encryption_key, backed_up_files));
 
     true
 }
 
-async fn recovery_worker(recovery_data: Vec<Vec<String>>, recovery_mode: String) {
+// Copies a backed up file to where it is restored, decrypting it if needed
+fn restore_file(original_file: &Path, destination_file: &Path, encryption_key: Option<&EncryptionKey>) -> std::io::Result<()> {
+    match encryption_key {
+        Some(key) if encryption_manager::is_encrypted(original_file) => {
+            let file_key = encryption_manager::get_file_key(original_file, key);
+            encryption_manager::decrypt_file(original_file, destination_file, &file_key).map(|_| ())
+        }
+        _ => std::fs::copy(original_file, destination_file).map(|_| ()),
+    }
+}
+
+// Files waiting to be extracted from archive volumes, and the metadata to restore once they are
+struct QueuedFiles {
+    volumes: HashMap<PathBuf, HashMap<String, PathBuf>>,
+    metadata: HashMap<PathBuf, FileMetadata>,
+}
+
+// Restores the recorded metadata of a restored file, logging anything that could not be restored
+fn restore_metadata(destination_file: &Path, metadata: Option<&FileMetadata>) {
+    if let Some(metadata) = metadata {
+        for warning in metadata_manager::apply_metadata(destination_file, metadata) {
+            println!("{}", warning);
+            RECOVERY_LOGS.lock().unwrap().push(warning);
+        }
+    }
+}
+
+// Restores a file, or queues it to be extracted along with the rest of the archive volume holding it.
+// Links that were backed up as links are recreated from their manifest entry.
+fn restore_or_queue(
+    original_file: &Path,
+    destination_file: &Path,
+    encryption_key: Option<&EncryptionKey>,
+    backed_up_file: Option<&ManifestEntry>,
+    queued_files: &mut QueuedFiles,
+) {
+    let destination_file_name = destination_file.to_string_lossy().to_string();
+    if let Some(link_target) = backed_up_file.and_then(|backed_up_file| backed_up_file.link_target.as_ref()) {
+        match link_manager::create_symlink(link_target, destination_file) {
+            Ok(_) => println!("Successfully recovered link: {}", destination_file_name),
+            Err(e) => {
+                println!("Error creating link {}: {}", destination_file_name, e);
+                RECOVERY_LOGS.lock().unwrap().push(format!("Could not recover {}, Error creating link to {}: {}", destination_file_name, link_target, e));
+            }
+        }
+        return;
+    }
+
+    let metadata = backed_up_file.and_then(|backed_up_file| backed_up_file.metadata.as_ref());
+    if let Some((volume, entry_name)) = archive_manager::split_archive_path(original_file) {
+        queued_files
+            .volumes
+            .entry(volume)
+            .or_default()
+            .insert(entry_name, destination_file.to_path_buf());
+        if let Some(metadata) = metadata {
+            queued_files.metadata.insert(destination_file.to_path_buf(), metadata.clone());
+        }
+        return;
+    }
+
+    match restore_file(original_file, destination_file, encryption_key) {
+        Ok(_) => {
+            println!("Successfully recovered file: {}", destination_file_name);
+            restore_metadata(destination_file, metadata);
+        }
+        Err(e) => {
+            println!("Error copying file to {}: {}", destination_file_name, e);
+            RECOVERY_LOGS.lock().unwrap().push(format!("Could not recover {}, Error copying file to {}: {}", destination_file_name, destination_file_name, e));
+        }
+    }
+}
+
+async fn recovery_worker(
+    recovery_data: Vec<Vec<String>>,
+    recovery_mode: String,
+    encryption_key: Option<EncryptionKey>,
+    backed_up_files: HashMap<String, ManifestEntry>,
+) {
     RECOVERY_LOGS.lock().unwrap().clear();
+    // Files in archives are extracted once every entry has been looked at, so each volume is read once
+    let mut queued_files = QueuedFiles {
+        volumes: HashMap::new(),
+        metadata: HashMap::new(),
+    };
     
     let mut file_index = 0;
     for entry in recovery_data.iter() {
@@ -106,7 +434,12 @@ async fn recovery_worker(recovery_data: Vec<Vec<String>>, recovery_mode: String)
         let original_file = std::path::Path::new(&original_file_name);
         let destination_file = std::path::Path::new(&destination_file_name);
 
-        if (!original_file.exists()){
+        let backed_up_file = backed_up_files.get(&original_file_name);
+        let metadata = backed_up_file.and_then(|backed_up_file| backed_up_file.metadata.as_ref());
+        let in_archive = archive_manager::split_archive_path(original_file).is_some();
+        let is_link = backed_up_file.map_or(false, |backed_up_file| backed_up_file.link_target.is_some());
+
+        if (!original_file.exists() && !in_archive && !is_link){
             println!("Original file does not exist: {}", original_file_name);
             RECOVERY_LOGS.lock().unwrap().push(format!("Could not recover {}, Original file does not exist: {}", destination_file_name, original_file_name));
             continue;
@@ -131,15 +464,7 @@ async fn recovery_worker(recovery_data: Vec<Vec<String>>, recovery_mode: String)
 
             println!("Destination file does not exist, creating new file: {}", destination_file_name);
             // Copy the original file to the destination
-            match std::fs::copy(&original_file, &destination_file) {
-                Ok(_) => {
-                    println!("Successfully recovered file: {}", destination_file_name);
-                }
-                Err(e) => {
-                    println!("Error copying file to {}: {}", destination_file_name, e);
-                    RECOVERY_LOGS.lock().unwrap().push(format!("Could not recover {}, Error copying file to {}: {}", destination_file_name, destination_file_name, e));
-                }
-            }
+            restore_or_queue(&original_file, &destination_file, encryption_key.as_ref(), backed_up_file, &mut queued_files);
 
             continue;
         }
@@ -152,38 +477,44 @@ async fn recovery_worker(recovery_data: Vec<Vec<String>>, recovery_mode: String)
         if recovery_mode == "Overwrite Existing Files" {
             println!("Overwriting existing file: {}", destination_file_name);
             // Copy the original file to the destination, overwriting it
-            match std::fs::copy(&original_file, &destination_file) {
-                Ok(_) => {
-                    println!("Successfully recovered file: {}", destination_file_name);
-                }
-                Err(e) => {
-                    println!("Error copying file to {}: {}", destination_file_name, e);
-                    RECOVERY_LOGS.lock().unwrap().push(format!("Could not recover {}, Error copying file to {}: {}", destination_file_name, destination_file_name, e));
-                }
-            }
+            restore_or_queue(&original_file, &destination_file, encryption_key.as_ref(), backed_up_file, &mut queued_files);
         }
 
         if recovery_mode == "Keep Most Recently Updated Files" {
-            let original_modified = get_last_modified_time(&original_file);
+            // The manifest has the time the source was modified, which copies made before metadata was kept lack
+            let original_modified = match backed_up_file {
+                Some(backed_up_file) => metadata
+                    .and_then(metadata_manager::get_modified_time)
+                    .or(Some(UNIX_EPOCH + Duration::from_secs(backed_up_file.modified))),
+                None => get_last_modified_time(&original_file),
+            };
             let destination_modified = get_last_modified_time(&destination_file);
 
             if (original_modified > destination_modified) {
                 println!("Original file is more recently updated. Overwriting: {}", destination_file_name);
                 // Copy the original file to the destination, overwriting it
-                match std::fs::copy(&original_file, &destination_file) {
-                    Ok(_) => {
-                        println!("Successfully recovered file: {}", destination_file_name);
-                    }
-                    Err(e) => {
-                        println!("Error copying file to {}: {}", destination_file_name, e);
-                        RECOVERY_LOGS.lock().unwrap().push(format!("Could not recover {}, Error copying file to {}: {}", destination_file_name, destination_file_name, e));
-                    }
-                }
+                restore_or_queue(&original_file, &destination_file, encryption_key.as_ref(), backed_up_file, &mut queued_files);
             } else {
                 println!("Destination file is more recently updated. Skipping: {}", destination_file_name);
             }
         }
     }
+
+    for (volume, files) in queued_files.volumes {
+        println!("Extracting {} files from {}", files.len(), volume.display());
+        for (destination_file, result) in archive_manager::extract_files(&volume, &files) {
+            match result {
+                Ok(()) => {
+                    println!("Successfully recovered file: {}", destination_file.display());
+                    restore_metadata(&destination_file, queued_files.metadata.get(&destination_file));
+                }
+                Err(e) => {
+                    println!("Error extracting file to {}: {}", destination_file.display(), e);
+                    RECOVERY_LOGS.lock().unwrap().push(format!("Could not recover {}, Error extracting file: {}", destination_file.display(), e));
+                }
+            }
+        }
+    }
     *RECOVERY_PROGRESS.lock().unwrap() = 1.0;
 }
 
@@ -0,0 +1,640 @@
+use crate::job_manager;
+use crate::storage_manager::{file_with_executable, read_json_file, write_json_file};
+use crate::structs::{JobInfo, Trigger};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use sysinfo::{System, MINIMUM_CPU_UPDATE_INTERVAL};
+use time::{Duration, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset};
+
+// Time triggers are run from 5-field cron expressions: minute hour day-of-month month day-of-week
+// Each field accepts *, single values, ranges (1-5), lists (1,15) and steps (*/15, 0-30/10).
+// Months and weekdays also accept names (JAN, MON), and both 0 and 7 mean Sunday.
+// Expressions are matched against local time, with the offset looked up for each fire time.
+// Legacy hourly, daily, weekly and monthly triggers are converted to cron expressions.
+// "time" triggers run the job, while "scrub" triggers take the same traits and check the job's backups instead.
+
+// Next fire time of each scheduled trigger, keyed by job UUID, trigger type and cron expression
+static NEXT_FIRE_TIMES: Lazy<Mutex<HashMap<String, OffsetDateTime>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+// Missed runs waiting for the computer to be idle, keyed like NEXT_FIRE_TIMES with the job UUID and trigger type as value
+static IDLE_RUNS: Lazy<Mutex<HashMap<String, (String, String)>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+// Due runs that have not started yet, such as while the job is still running, keyed like IDLE_RUNS
+static PENDING_RUNS: Lazy<Mutex<HashMap<String, (String, String)>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+// A fire time this long in the past was missed rather than just reached
+const MISSED_AFTER: Duration = Duration::minutes(2);
+// Global CPU usage (percent) below which the computer counts as idle
+const IDLE_CPU_USAGE: f32 = 20.0;
+
+const MONTH_NAMES: [&str; 12] = [
+    "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+];
+const WEEKDAY_NAMES: [&str; 7] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+const TIMES_INDEX: [&str; 24] = [
+    "12 AM", "1 AM", "2 AM", "3 AM", "4 AM", "5 AM", "6 AM", "7 AM", "8 AM", "9 AM", "10 AM",
+    "11 AM", "12 PM", "1 PM", "2 PM", "3 PM", "4 PM", "5 PM", "6 PM", "7 PM", "8 PM", "9 PM",
+    "10 PM", "11 PM",
+];
+const WEEKDAY_INDEX: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+
+pub struct CronSchedule {
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days_of_month: Vec<bool>,
+    months: Vec<bool>,
+    days_of_week: Vec<bool>,
+    day_of_month_restricted: bool,
+    day_of_week_restricted: bool,
+}
+
+fn parse_value(value: &str, names: &[&str], name_offset: u32) -> Result<u32, String> {
+    if let Ok(number) = value.parse::<u32>() {
+        return Ok(number);
+    }
+    names
+        .iter()
+        .position(|name| name.eq_ignore_ascii_case(value))
+        .map(|index| index as u32 + name_offset)
+        .ok_or(format!("Invalid value '{}'", value))
+}
+
+// Parses one cron field into a list of allowed values from 0 to max
+fn parse_field(
+    field: &str,
+    min: u32,
+    max: u32,
+    names: &[&str],
+    name_offset: u32,
+) -> Result<Vec<bool>, String> {
+    let mut allowed = vec![false; max as usize + 1];
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step = step
+                    .parse::<u32>()
+                    .map_err(|_| format!("Invalid step '{}'", step))?;
+                if step == 0 {
+                    return Err(String::from("Step cannot be 0"));
+                }
+                (range, step)
+            }
+            None => (part, 1),
+        };
+
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (
+                parse_value(start, names, name_offset)?,
+                parse_value(end, names, name_offset)?,
+            )
+        } else {
+            let value = parse_value(range, names, name_offset)?;
+            // A single value with a step runs from that value to the end of the range
+            if part.contains('/') {
+                (value, max)
+            } else {
+                (value, value)
+            }
+        };
+
+        if start < min || end > max || start > end {
+            return Err(format!(
+                "'{}' is outside the allowed range {}-{}",
+                part, min, max
+            ));
+        }
+
+        let mut value = start;
+        while value <= end {
+            allowed[value as usize] = true;
+            value += step;
+        }
+    }
+
+    Ok(allowed)
+}
+
+impl CronSchedule {
+    pub fn parse(expression: &str) -> Result<CronSchedule, String> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!(
+                "Expected 5 fields (minute hour day-of-month month day-of-week), found {}",
+                fields.len()
+            ));
+        }
+
+        let mut days_of_week = parse_field(fields[4], 0, 7, &WEEKDAY_NAMES, 0)
+            .map_err(|e| format!("Day of week: {}", e))?;
+        // 7 is another name for Sunday
+        if days_of_week[7] {
+            days_of_week[0] = true;
+        }
+        days_of_week.truncate(7);
+
+        Ok(CronSchedule {
+            minutes: parse_field(fields[0], 0, 59, &[], 0).map_err(|e| format!("Minute: {}", e))?,
+            hours: parse_field(fields[1], 0, 23, &[], 0).map_err(|e| format!("Hour: {}", e))?,
+            days_of_month: parse_field(fields[2], 1, 31, &[], 0)
+                .map_err(|e| format!("Day of month: {}", e))?,
+            months: parse_field(fields[3], 1, 12, &MONTH_NAMES, 1)
+                .map_err(|e| format!("Month: {}", e))?,
+            days_of_week,
+            day_of_month_restricted: !fields[2].starts_with('*'),
+            day_of_week_restricted: !fields[4].starts_with('*'),
+        })
+    }
+
+    fn day_matches(&self, time: PrimitiveDateTime) -> bool {
+        let day_of_month = self.days_of_month[time.day() as usize];
+        let day_of_week = self.days_of_week[time.weekday().number_days_from_sunday() as usize];
+
+        // As in cron, when both day fields are restricted either one matching is enough
+        if self.day_of_month_restricted && self.day_of_week_restricted {
+            day_of_month || day_of_week
+        } else {
+            day_of_month && day_of_week
+        }
+    }
+
+    // Gets the first time after the given time that matches the schedule in local time.
+    // The local offset is looked up for every fire time, so schedules keep their wall clock times across
+    // daylight saving time changes.
+    pub fn next_fire_time(&self, after: OffsetDateTime) -> Option<OffsetDateTime> {
+        self.next_fire_time_with(after, &|time| UtcOffset::local_offset_at(time).unwrap_or(after.offset()))
+    }
+
+    // Gets the next fire time, with offset_at giving the UTC offset of local time at any point in time
+    fn next_fire_time_with(
+        &self,
+        after: OffsetDateTime,
+        offset_at: &dyn Fn(OffsetDateTime) -> UtcOffset,
+    ) -> Option<OffsetDateTime> {
+        // Schedules are matched against the local wall clock
+        let local_after = after.to_offset(offset_at(after));
+        let mut time = PrimitiveDateTime::new(local_after.date(), local_after.time())
+            .replace_second(0)
+            .ok()?
+            .replace_nanosecond(0)
+            .ok()?
+            + Duration::minutes(1);
+        // Expressions such as "0 0 31 2 *" never match, so give up after a few years
+        let limit = time + Duration::days(366 * 5);
+
+        while time <= limit {
+            if !self.months[time.month() as u8 as usize] {
+                let first_of_month = time.replace_day(1).ok()?.replace_time(Time::MIDNIGHT);
+                time = if time.month() == time::Month::December {
+                    first_of_month
+                        .replace_year(time.year() + 1)
+                        .ok()?
+                        .replace_month(time::Month::January)
+                        .ok()?
+                } else {
+                    first_of_month.replace_month(time.month().next()).ok()?
+                };
+                continue;
+            }
+            if !self.day_matches(time) {
+                time = time.replace_time(Time::MIDNIGHT) + Duration::days(1);
+                continue;
+            }
+            if !self.hours[time.hour() as usize] {
+                time = time.replace_minute(0).ok()? + Duration::hours(1);
+                continue;
+            }
+            if !self.minutes[time.minute() as usize] {
+                time += Duration::minutes(1);
+                continue;
+            }
+            // A local time repeated when the clocks go back can resolve to a time already passed
+            let fire_time = resolve_local_time(time, offset_at);
+            if fire_time > after {
+                return Some(fire_time);
+            }
+            time += Duration::minutes(1);
+        }
+
+        None
+    }
+}
+
+// Gets the point in time a local wall clock time refers to. A time that happens twice when the clocks go back
+// is its first occurrence, and a time skipped when the clocks go forward is read with the offset from before
+// the change, so it falls in the hour after the change instead of being missed.
+fn resolve_local_time(time: PrimitiveDateTime, offset_at: &dyn Fn(OffsetDateTime) -> UtcOffset) -> OffsetDateTime {
+    let offset_before = offset_at(time.assume_utc() - Duration::days(1));
+    let offset_after = offset_at(time.assume_utc() + Duration::days(1));
+    [offset_before, offset_after]
+        .into_iter()
+        .map(|offset| time.assume_offset(offset))
+        .filter(|candidate| offset_at(*candidate) == candidate.offset())
+        .min()
+        .unwrap_or(time.assume_offset(offset_before))
+}
+
+fn get_hour_from_label(label: &str) -> Option<usize> {
+    TIMES_INDEX.iter().position(|time| *time == label)
+}
+
+// Gets the cron expression a time trigger runs on, converting the legacy
+// hourly, daily, weekly and monthly triggers
+pub fn get_trigger_cron(trigger: &Trigger) -> Option<String> {
+    if trigger.trigger_type != "time" && trigger.trigger_type != "scrub" {
+        return None;
+    }
+
+    let event = trigger.traits.event.clone()?;
+    let times = trigger.traits.time.clone().unwrap_or_default();
+
+    match event.as_str() {
+        "cron" => times.first().cloned(),
+        "hourly" => Some(String::from("0 * * * *")),
+        "daily" => {
+            let hour = get_hour_from_label(times.first()?)?;
+            Some(format!("0 {} * * *", hour))
+        }
+        "weekly" => {
+            let weekday_label = times.first()?;
+            let weekday = WEEKDAY_INDEX.iter().position(|day| day == weekday_label)?;
+            let hour = get_hour_from_label(times.get(1)?)?;
+            Some(format!("0 {} * * {}", hour, weekday))
+        }
+        "monthly" => {
+            let day = times.first()?.parse::<u8>().ok()?;
+            let hour = get_hour_from_label(times.get(1)?)?;
+            Some(format!("0 {} {} * *", hour, day))
+        }
+        _ => None,
+    }
+}
+
+fn get_schedule_key(job_uuid: &str, trigger_type: &str, expression: &str) -> String {
+    if trigger_type == "time" {
+        format!("{}|{}", job_uuid, expression)
+    } else {
+        format!("{}|{}|{}", job_uuid, trigger_type, expression)
+    }
+}
+
+// Last time each scheduled trigger ran or was skipped, as seconds since UNIX_EPOCH
+fn get_last_runs() -> HashMap<String, i64> {
+    read_json_file::<HashMap<String, i64>>(file_with_executable("job_schedule.json"))
+        .unwrap_or_else(|_| HashMap::new())
+}
+
+fn set_last_runs(last_runs: &HashMap<String, i64>) -> bool {
+    write_json_file(file_with_executable("job_schedule.json"), last_runs).is_ok()
+}
+
+// Gets the fire time a trigger missed while Archway was closed, if any
+fn get_missed_fire_time(
+    schedule: &CronSchedule,
+    last_run: Option<&i64>,
+    now: OffsetDateTime,
+) -> Option<OffsetDateTime> {
+    let last_run = OffsetDateTime::from_unix_timestamp(*last_run?)
+        .ok()?
+        .to_offset(now.offset());
+    schedule
+        .next_fire_time(last_run)
+        .filter(|fire_time| *fire_time <= now)
+}
+
+// Gets the jobs with a time or scrub trigger that is due, along with the trigger type, and schedules each
+// trigger's next run. Fire times missed while the computer was asleep or Archway was closed are run once,
+// queued for the next idle moment or skipped, depending on the trigger's catch_up policy.
+// Due runs stay due until record_run is called for them, so a run that cannot start yet is tried again.
+pub fn get_due_jobs(jobs: &[JobInfo], now: OffsetDateTime) -> Vec<(JobInfo, String)> {
+    let mut next_fire_times = NEXT_FIRE_TIMES.lock().unwrap();
+    let mut idle_runs = IDLE_RUNS.lock().unwrap();
+    let mut pending_runs = PENDING_RUNS.lock().unwrap();
+    let mut last_runs = get_last_runs();
+    let mut last_runs_changed = false;
+    let mut active_keys: Vec<String> = Vec::new();
+
+    for job in jobs {
+        for trigger in &job.triggers {
+            let expression = match get_trigger_cron(trigger) {
+                Some(expression) => expression,
+                None => continue,
+            };
+            let schedule = match CronSchedule::parse(&expression) {
+                Ok(schedule) => schedule,
+                Err(e) => {
+                    println!(
+                        "Invalid schedule '{}' for job {}: {}",
+                        expression, job.job_name, e
+                    );
+                    continue;
+                }
+            };
+
+            let key = get_schedule_key(&job.uuid, &trigger.trigger_type, &expression);
+            active_keys.push(key.clone());
+
+            let fire_time = match next_fire_times.get(&key) {
+                Some(fire_time) => Some(*fire_time),
+                None => get_missed_fire_time(&schedule, last_runs.get(&key), now),
+            };
+
+            match fire_time {
+                Some(fire_time) if fire_time <= now => {
+                    let catch_up = trigger.traits.catch_up.clone().unwrap_or("skip".to_string());
+                    let missed = now - fire_time > MISSED_AFTER;
+
+                    if !missed || catch_up == "immediate" {
+                        if missed {
+                            println!("Catching up missed run of job {} ({})", job.job_name, expression);
+                        }
+                        // The last run is recorded once the job starts
+                        pending_runs.insert(key.clone(), (job.uuid.clone(), trigger.trigger_type.clone()));
+                    } else if catch_up == "idle" {
+                        // The last run is recorded once the job runs, so a queued run survives a restart
+                        println!("Queueing missed run of job {} ({}) for the next idle moment", job.job_name, expression);
+                        idle_runs.insert(key.clone(), (job.uuid.clone(), trigger.trigger_type.clone()));
+                    } else {
+                        println!("Skipping missed run of job {} ({})", job.job_name, expression);
+                        last_runs.insert(key.clone(), now.unix_timestamp());
+                        last_runs_changed = true;
+                    }
+                }
+                Some(_) => continue,
+                None => {
+                    // New triggers start counting from now so later missed runs can be detected
+                    if !last_runs.contains_key(&key) {
+                        last_runs.insert(key.clone(), now.unix_timestamp());
+                        last_runs_changed = true;
+                    }
+                }
+            }
+
+            match schedule.next_fire_time(now) {
+                Some(next_fire_time) => {
+                    println!(
+                        "Next run of job {} ({}): {}",
+                        job.job_name, expression, next_fire_time
+                    );
+                    next_fire_times.insert(key, next_fire_time);
+                }
+                None => {
+                    next_fire_times.remove(&key);
+                }
+            }
+        }
+    }
+
+    // Forget triggers of jobs that were removed or edited
+    next_fire_times.retain(|key, _| active_keys.contains(key));
+    idle_runs.retain(|key, _| active_keys.contains(key));
+    pending_runs.retain(|key, _| active_keys.contains(key));
+    let last_runs_count = last_runs.len();
+    last_runs.retain(|key, _| active_keys.contains(key));
+
+    if last_runs_changed || last_runs.len() != last_runs_count {
+        set_last_runs(&last_runs);
+    }
+
+    get_queued_jobs(jobs, &pending_runs)
+}
+
+// Gets the job and trigger type of each queued run, listing a job once per trigger type
+fn get_queued_jobs(jobs: &[JobInfo], runs: &HashMap<String, (String, String)>) -> Vec<(JobInfo, String)> {
+    let mut queued_jobs: Vec<(JobInfo, String)> = Vec::new();
+    for (job_uuid, trigger_type) in runs.values() {
+        if queued_jobs
+            .iter()
+            .any(|(queued_job, queued_type)| queued_job.uuid == *job_uuid && queued_type == trigger_type)
+        {
+            continue;
+        }
+        if let Some(job) = jobs.iter().find(|job| job.uuid == *job_uuid) {
+            queued_jobs.push((job.clone(), trigger_type.clone()));
+        }
+    }
+    queued_jobs
+}
+
+// Records that a scheduled job or scrub started, so its due and idle runs are not run again
+pub fn record_run(job_uuid: &str, trigger_type: &str, now: OffsetDateTime) {
+    let mut pending_runs = PENDING_RUNS.lock().unwrap();
+    let mut idle_runs = IDLE_RUNS.lock().unwrap();
+    let keys: Vec<String> = pending_runs
+        .iter()
+        .chain(idle_runs.iter())
+        .filter(|(_, (run_uuid, run_type))| run_uuid == job_uuid && run_type == trigger_type)
+        .map(|(key, _)| key.clone())
+        .collect();
+    if keys.is_empty() {
+        return;
+    }
+
+    let mut last_runs = get_last_runs();
+    for key in keys {
+        pending_runs.remove(&key);
+        idle_runs.remove(&key);
+        last_runs.insert(key, now.unix_timestamp());
+    }
+    set_last_runs(&last_runs);
+}
+
+// Checks whether no jobs are running and the computer is mostly idle
+fn is_system_idle() -> bool {
+    if job_manager::get_active_jobs() > 0 {
+        return false;
+    }
+
+    let mut system = System::new();
+    system.refresh_cpu_usage();
+    std::thread::sleep(MINIMUM_CPU_UPDATE_INTERVAL);
+    system.refresh_cpu_usage();
+    system.global_cpu_usage() < IDLE_CPU_USAGE
+}
+
+// Gets the jobs with missed runs queued for the next idle moment, along with the trigger type, if the computer is idle now.
+// The runs stay queued until record_run is called for them.
+pub fn get_idle_jobs(jobs: &[JobInfo]) -> Vec<(JobInfo, String)> {
+    if IDLE_RUNS.lock().unwrap().is_empty() || !is_system_idle() {
+        return Vec::new();
+    }
+
+    get_queued_jobs(jobs, &IDLE_RUNS.lock().unwrap())
+}
+
+// Gets the time until the next scheduled trigger fires, if any are scheduled
+pub fn get_time_until_next_fire(now: OffsetDateTime) -> Option<std::time::Duration> {
+    let next_fire_times = NEXT_FIRE_TIMES.lock().unwrap();
+    let next_fire_time = next_fire_times.values().min()?;
+    let seconds = (*next_fire_time - now).whole_seconds().max(0) as u64;
+    Some(std::time::Duration::from_secs(seconds))
+}
+
+// Gets the next time a cron expression fires, as seconds since UNIX_EPOCH
+pub fn get_next_fire_time(expression: &str) -> Result<i64, String> {
+    let schedule = CronSchedule::parse(expression)?;
+    let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+    schedule
+        .next_fire_time(now)
+        .map(|next_fire_time| next_fire_time.unix_timestamp())
+        .ok_or(String::from("The schedule never runs"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::{Date, Month};
+
+    fn at(year: i32, month: Month, day: u8, hour: u8, minute: u8) -> OffsetDateTime {
+        Date::from_calendar_date(year, month, day)
+            .unwrap()
+            .with_hms(hour, minute, 0)
+            .unwrap()
+            .assume_utc()
+    }
+
+    fn utc(_: OffsetDateTime) -> UtcOffset {
+        UtcOffset::UTC
+    }
+
+    // Central European time, with clocks going forward on 2024-03-31 and back on 2024-10-27
+    fn central_european(time: OffsetDateTime) -> UtcOffset {
+        if time >= at(2024, Month::March, 31, 1, 0) && time < at(2024, Month::October, 27, 1, 0) {
+            UtcOffset::from_hms(2, 0, 0).unwrap()
+        } else {
+            UtcOffset::from_hms(1, 0, 0).unwrap()
+        }
+    }
+
+    fn allowed(values: &[bool]) -> Vec<usize> {
+        values
+            .iter()
+            .enumerate()
+            .filter(|(_, allowed)| **allowed)
+            .map(|(value, _)| value)
+            .collect()
+    }
+
+    #[test]
+    fn parses_ranges_lists_and_steps() {
+        let schedule = CronSchedule::parse("0-30/10,45 */6 1,15 * *").unwrap();
+        assert_eq!(allowed(&schedule.minutes), vec![0, 10, 20, 30, 45]);
+        assert_eq!(allowed(&schedule.hours), vec![0, 6, 12, 18]);
+        assert_eq!(allowed(&schedule.days_of_month), vec![1, 15]);
+        assert_eq!(allowed(&schedule.months), (1..=12).collect::<Vec<usize>>());
+
+        // A single value with a step runs to the end of the range
+        let schedule = CronSchedule::parse("50/5 * * * *").unwrap();
+        assert_eq!(allowed(&schedule.minutes), vec![50, 55]);
+    }
+
+    #[test]
+    fn parses_names_and_sunday_as_seven() {
+        let schedule = CronSchedule::parse("0 9 * jan-MAR MON-FRI").unwrap();
+        assert_eq!(allowed(&schedule.months), vec![1, 2, 3]);
+        assert_eq!(allowed(&schedule.days_of_week), vec![1, 2, 3, 4, 5]);
+
+        let schedule = CronSchedule::parse("0 0 * * 7").unwrap();
+        assert_eq!(allowed(&schedule.days_of_week), vec![0]);
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        assert!(CronSchedule::parse("").is_err());
+        assert!(CronSchedule::parse("* * * *").is_err());
+        assert!(CronSchedule::parse("* * * * * *").is_err());
+        assert!(CronSchedule::parse("60 * * * *").is_err());
+        assert!(CronSchedule::parse("* 24 * * *").is_err());
+        assert!(CronSchedule::parse("0 0 0 * *").is_err());
+        assert!(CronSchedule::parse("0 0 * 13 *").is_err());
+        assert!(CronSchedule::parse("5-1 * * * *").is_err());
+        assert!(CronSchedule::parse("*/0 * * * *").is_err());
+        assert!(CronSchedule::parse("* * * * FOO").is_err());
+        assert!(CronSchedule::parse("0 0 * MON *").is_err());
+    }
+
+    #[test]
+    fn finds_the_next_fire_time() {
+        let schedule = CronSchedule::parse("*/15 * * * *").unwrap();
+        let after = at(2024, Month::June, 1, 10, 7) + Duration::seconds(30);
+        assert_eq!(schedule.next_fire_time_with(after, &utc), Some(at(2024, Month::June, 1, 10, 15)));
+        // A time that matches the schedule is not its own next fire time
+        let after = at(2024, Month::June, 1, 10, 15);
+        assert_eq!(schedule.next_fire_time_with(after, &utc), Some(at(2024, Month::June, 1, 10, 30)));
+
+        // 2024-06-01 is a Saturday
+        let schedule = CronSchedule::parse("0 9 * * MON-FRI").unwrap();
+        let after = at(2024, Month::June, 1, 12, 0);
+        assert_eq!(schedule.next_fire_time_with(after, &utc), Some(at(2024, Month::June, 3, 9, 0)));
+
+        let schedule = CronSchedule::parse("0 0 1 1 *").unwrap();
+        assert_eq!(schedule.next_fire_time_with(after, &utc), Some(at(2025, Month::January, 1, 0, 0)));
+
+        let schedule = CronSchedule::parse("30 23 31 12 *").unwrap();
+        let after = at(2024, Month::December, 31, 23, 45);
+        assert_eq!(schedule.next_fire_time_with(after, &utc), Some(at(2025, Month::December, 31, 23, 30)));
+    }
+
+    #[test]
+    fn matches_either_restricted_day_field() {
+        let after = at(2024, Month::June, 1, 12, 0);
+        let schedule = CronSchedule::parse("0 0 15 * *").unwrap();
+        assert_eq!(schedule.next_fire_time_with(after, &utc), Some(at(2024, Month::June, 15, 0, 0)));
+        let schedule = CronSchedule::parse("0 0 15 * MON").unwrap();
+        assert_eq!(schedule.next_fire_time_with(after, &utc), Some(at(2024, Month::June, 3, 0, 0)));
+    }
+
+    #[test]
+    fn handles_rare_and_impossible_dates() {
+        let after = at(2024, Month::June, 1, 12, 0);
+        let schedule = CronSchedule::parse("0 0 29 2 *").unwrap();
+        assert_eq!(schedule.next_fire_time_with(after, &utc), Some(at(2028, Month::February, 29, 0, 0)));
+        let schedule = CronSchedule::parse("0 0 31 2 *").unwrap();
+        assert_eq!(schedule.next_fire_time_with(after, &utc), None);
+    }
+
+    #[test]
+    fn keeps_local_times_across_daylight_saving_changes() {
+        let schedule = CronSchedule::parse("0 9 * * *").unwrap();
+        let after = at(2024, Month::March, 30, 12, 0);
+        assert_eq!(
+            schedule.next_fire_time_with(after, &central_european),
+            Some(at(2024, Month::March, 31, 7, 0))
+        );
+        let after = at(2024, Month::October, 26, 12, 0);
+        assert_eq!(
+            schedule.next_fire_time_with(after, &central_european),
+            Some(at(2024, Month::October, 27, 8, 0))
+        );
+    }
+
+    #[test]
+    fn handles_skipped_and_repeated_local_times() {
+        let schedule = CronSchedule::parse("30 2 * * *").unwrap();
+        // 02:30 does not exist on 2024-03-31, so the run falls in the hour after the change (03:30)
+        let after = at(2024, Month::March, 30, 12, 0);
+        assert_eq!(
+            schedule.next_fire_time_with(after, &central_european),
+            Some(at(2024, Month::March, 31, 1, 30))
+        );
+
+        // 02:30 happens twice on 2024-10-27, and only the first one fires
+        let after = at(2024, Month::October, 26, 12, 0);
+        let first = schedule.next_fire_time_with(after, &central_european).unwrap();
+        assert_eq!(first, at(2024, Month::October, 27, 0, 30));
+        assert_eq!(
+            schedule.next_fire_time_with(first, &central_european),
+            Some(at(2024, Month::October, 28, 1, 30))
+        );
+    }
+}
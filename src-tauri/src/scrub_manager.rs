@@ -0,0 +1,295 @@
+use crate::job_manager::{get_app_handle, is_job_running};
+use crate::log_manager::job_log;
+use crate::structs::{JobInfo, ScrubReport};
+use crate::{archive_manager, copy_manager, dedup_manager, drive_manager, job_manager, manifest_manager, settings_manager, storage_manager};
+use once_cell::sync::Lazy;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri_plugin_notification::NotificationExt;
+
+/*
+A scrub re-reads every file of a job's retained runs on the output drive and compares it to the
+hash recorded in that run's manifest, so backups that have silently bit-rotted are found before
+they are needed. Jobs with missing or changed files are marked with the "degraded" health.
+Scrubs run from the Job Manager or from triggers with the "scrub" trigger_type.
+ */
+
+// Jobs being scrubbed, which are not started until the scrub finishes
+static SCRUBBING_JOBS: Lazy<Mutex<HashSet<String>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+pub fn is_scrubbing(uuid: &str) -> bool {
+    SCRUBBING_JOBS.lock().unwrap().contains(uuid)
+}
+
+// Starts scrubbing a job's backups on a separate thread
+pub fn start_scrub(uuid: String) -> bool {
+    if is_job_running(&uuid) || is_scrubbing(&uuid) {
+        println!("Job with UUID {} is busy, not scrubbing.", uuid);
+        return false;
+    }
+    tauri::async_runtime::spawn_blocking(move || {
+        scrub_job(&uuid);
+    });
+    true
+}
+
+// Checks that the job's output drive is connected, and is the job's output device
+fn check_output_drive(job_info: &JobInfo) -> Result<(), String> {
+    let drive = drive_manager::get_root_drive(&job_info.output_dir)
+        .ok_or(String::from("Could not determine the output drive."))?;
+    if !PathBuf::from(&job_info.output_dir).exists() {
+        return Err(String::from("The output drive is not connected."));
+    }
+    if job_info.output_device != "special:any"
+        && drive_manager::read_drive_uuid(&drive).unwrap_or_default() != job_info.output_device
+    {
+        return Err(String::from("The connected drive is not the job's output device."));
+    }
+    Ok(())
+}
+
+// Gets the folders of a job's completed runs that are still kept on the output drive
+fn get_retained_run_dirs(job_info: &JobInfo) -> Vec<PathBuf> {
+    let run_dirs: Vec<PathBuf> = if job_info.storage_layout.clone().unwrap_or_default() == "dedup" {
+        let store_dir = dedup_manager::get_store_dir(&job_info.output_dir, &job_info.uuid);
+        dedup_manager::get_snapshot_dirs(&store_dir)
+    } else {
+        let base_dir = PathBuf::from(&job_info.output_dir).join(format!("archway-{}", job_info.uuid));
+        let mut run_dirs = vec![base_dir.clone()];
+        for number in 1..=job_info.copies {
+            run_dirs.push(base_dir.with_file_name(format!("archway-{}-{}", job_info.uuid, number)));
+        }
+        run_dirs
+    };
+
+    run_dirs
+        .into_iter()
+        .filter(|run_dir| manifest_manager::get_manifest_path(run_dir).exists())
+        .collect()
+}
+
+fn hash_file(path: &PathBuf) -> io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(manifest_manager::hash_to_hex(&hasher.finalize()))
+}
+
+// Re-hashes a stored file, returning a description of the problem if it does not match its manifest
+fn check_file(path: &PathBuf, hashes: &[String]) -> Option<String> {
+    if !path.exists() {
+        return Some(format!("Missing file: {}", path.display()));
+    }
+    match hash_file(path) {
+        Ok(file_hash) if hashes.contains(&file_hash) => None,
+        Ok(_) => Some(format!("Changed file: {}", path.display())),
+        Err(e) => Some(format!("Unreadable file: {} ({})", path.display(), e)),
+    }
+}
+
+// Re-hashes the files of an archive volume, returning a description of each file that does not match its manifest
+fn check_volume(volume: &Path, files: &[(String, String)]) -> Vec<String> {
+    if !volume.exists() {
+        return vec![format!("Missing file: {}", volume.display())];
+    }
+    let volume_hashes = match archive_manager::hash_volume(volume) {
+        Ok(volume_hashes) => volume_hashes,
+        Err(e) => return vec![format!("Unreadable file: {} ({})", volume.display(), e)],
+    };
+    files
+        .iter()
+        .filter_map(|(entry_name, hash)| match volume_hashes.get(entry_name) {
+            Some(entry_hash) if manifest_manager::hash_to_hex(entry_hash) == *hash => None,
+            Some(_) => Some(format!("Changed file: {} in {}", entry_name, volume.display())),
+            None => Some(format!("Missing file: {} in {}", entry_name, volume.display())),
+        })
+        .collect()
+}
+
+fn run_scrub(job_info: &JobInfo, log_level: &str) -> ScrubReport {
+    let mut report = ScrubReport {
+        runs_checked: 0,
+        files_checked: 0,
+        files_unchecked: 0,
+        problems: Vec::new(),
+        error: None,
+    };
+
+    if let Err(e) = check_output_drive(job_info) {
+        report.error = Some(e);
+        return report;
+    }
+
+    let run_dirs = get_retained_run_dirs(job_info);
+    if run_dirs.is_empty() {
+        report.error = Some(String::from("No completed backups were found on the output drive."));
+        return report;
+    }
+
+    // Blobs shared by several snapshots of a deduplicated store are only checked once
+    let store_dir = dedup_manager::get_store_dir_from_snapshot(&run_dirs[0]);
+    let mut seen_files: HashMap<PathBuf, usize> = HashMap::new();
+    let mut files_to_check: Vec<(PathBuf, Vec<String>)> = Vec::new();
+    // Archived files are checked a volume at a time, so each volume is read once
+    let mut volumes_to_check: HashMap<PathBuf, Vec<(String, String)>> = HashMap::new();
+    for run_dir in &run_dirs {
+        let manifest = match manifest_manager::read_manifest(run_dir) {
+            Some(manifest) => manifest,
+            None => {
+                report.problems.push(format!("Unreadable manifest: {}", run_dir.display()));
+                continue;
+            }
+        };
+        report.runs_checked += 1;
+
+        for entry in manifest.files {
+            // Links kept as links have no contents to check
+            if entry.link_target.is_some() {
+                continue;
+            }
+            let hash = match entry.hash {
+                Some(hash) => hash,
+                None => {
+                    report.files_unchecked += 1;
+                    continue;
+                }
+            };
+            let path = match store_dir.as_ref() {
+                Some(store_dir) => match dedup_manager::get_blob_path(store_dir, &hash) {
+                    Ok(path) => path,
+                    Err(e) => {
+                        report.problems.push(format!("{}: {}", entry.source, e));
+                        continue;
+                    }
+                },
+                None => run_dir.join(&entry.output),
+            };
+            if let Some((volume, entry_name)) = archive_manager::split_archive_path(&path) {
+                volumes_to_check.entry(volume).or_default().push((entry_name, hash));
+                continue;
+            }
+            // Encrypted files are checked as stored, so no key is needed.
+            // A blob is stored again when encryption is turned on or off or the salt changes,
+            // so it may match the hash recorded by any snapshot using it.
+            let hashes: Vec<String> = entry.stored_hash.into_iter().chain([hash]).collect();
+            match seen_files.get(&path) {
+                Some(index) => files_to_check[*index].1.extend(hashes),
+                None => {
+                    seen_files.insert(path.clone(), files_to_check.len());
+                    files_to_check.push((path, hashes));
+                }
+            }
+        }
+    }
+
+    let volumes_to_check: Vec<(PathBuf, Vec<(String, String)>)> = volumes_to_check.into_iter().collect();
+    job_log(
+        &job_info.uuid,
+        &format!(
+            "Scrubbing {} files in {} backups",
+            files_to_check.len()
+                + volumes_to_check.iter().map(|(_, files)| files.len()).sum::<usize>(),
+            report.runs_checked
+        ),
+        "STEP",
+        log_level.to_string(),
+    );
+
+    let results = copy_manager::run_tasks(
+        &files_to_check,
+        copy_manager::get_worker_count(),
+        || false,
+        |(path, hashes)| Ok::<Option<String>, String>(check_file(path, hashes)),
+    );
+    for result in results.into_iter().flatten() {
+        report.files_checked += 1;
+        if let Ok(Some(problem)) = result {
+            job_log(&job_info.uuid, &problem, "ERROR", log_level.to_string());
+            report.problems.push(problem);
+        }
+    }
+
+    let volume_results = copy_manager::run_tasks(
+        &volumes_to_check,
+        copy_manager::get_worker_count(),
+        || false,
+        |(volume, files)| Ok::<Vec<String>, String>(check_volume(volume, files)),
+    );
+    for ((_, files), result) in volumes_to_check.iter().zip(volume_results) {
+        report.files_checked += files.len() as u64;
+        if let Some(Ok(problems)) = result {
+            for problem in problems {
+                job_log(&job_info.uuid, &problem, "ERROR", log_level.to_string());
+                report.problems.push(problem);
+            }
+        }
+    }
+
+    report
+}
+
+// Checks every retained backup of a job against its manifests and updates the job health
+pub fn scrub_job(uuid: &str) -> ScrubReport {
+    let log_level = settings_manager::get_settings().log_level.unwrap();
+    let start_guard = job_manager::START_LOCK.lock().unwrap();
+    if is_job_running(uuid) || !SCRUBBING_JOBS.lock().unwrap().insert(uuid.to_string()) {
+        return ScrubReport {
+            runs_checked: 0,
+            files_checked: 0,
+            files_unchecked: 0,
+            problems: Vec::new(),
+            error: Some(String::from("The job is running or already being checked.")),
+        };
+    }
+
+    drop(start_guard);
+
+    let job_info = storage_manager::get_job_by_uuid(uuid);
+    job_log(uuid, "Backup scrub started.", "START", log_level.clone());
+    let report = run_scrub(&job_info, &log_level);
+
+    match &report.error {
+        Some(e) => {
+            println!("Could not scrub job {}: {}", job_info.job_name, e);
+            job_log(uuid, &format!("Backup scrub skipped. {}", e), "STOP", log_level.clone());
+        }
+        None if !report.problems.is_empty() => {
+            storage_manager::set_job_health_by_uuid(uuid, "degraded");
+            job_log(
+                uuid,
+                &format!("Backup scrub found {} problems.", report.problems.len()),
+                "STOP",
+                log_level.clone(),
+            );
+            get_app_handle()
+                .notification()
+                .builder()
+                .title("Backup Problems Found: ".to_owned() + &job_info.job_name)
+                .body(format!(
+                    "{} backed up files are missing or damaged. See the job log for details.",
+                    report.problems.len()
+                ))
+                .show()
+                .unwrap_or_else(|e| println!("Failed to send notification: {}", e));
+        }
+        None => {
+            // A clean scrub clears an earlier degraded health, but not a failed run
+            if storage_manager::get_job_health_by_uuid(uuid).starts_with("degraded") {
+                storage_manager::set_job_health_by_uuid(uuid, "good");
+            }
+            job_log(
+                uuid,
+                &format!("Backup scrub completed. {} files verified.", report.files_checked),
+                "STOP",
+                log_level.clone(),
+            );
+        }
+    }
+
+    SCRUBBING_JOBS.lock().unwrap().remove(uuid);
+    report
+}
@@ -2,17 +2,20 @@ use crate::storage_manager::{file_with_executable, read_json_file};
 use serde::{Deserialize, Serialize};
 use tauri_plugin_autostart::ManagerExt;
 use crate::job_manager::get_app_handle;
+use crate::copy_manager;
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct SettingsJSON {
     pub run_on_startup: Option<bool>,
     pub log_level: Option<String>,
+    pub copy_workers: Option<u32>,
 }
 
 fn fill_default_settings(settings: &mut SettingsJSON) {
     let default_settings = SettingsJSON {
         run_on_startup: Option::from(true),
         log_level: Option::from("low".to_string()),
+        copy_workers: Option::from(copy_manager::DEFAULT_COPY_WORKERS),
     };
 
     if settings.run_on_startup.is_none() {
@@ -21,6 +24,9 @@ fn fill_default_settings(settings: &mut SettingsJSON) {
     if settings.log_level.is_none() {
         settings.log_level = default_settings.log_level;
     }
+    if settings.copy_workers.is_none() {
+        settings.copy_workers = default_settings.copy_workers;
+    }
 }
 
 pub fn get_settings() -> SettingsJSON {
@@ -30,6 +36,7 @@ pub fn get_settings() -> SettingsJSON {
                 let mut default_settings = SettingsJSON {
                     run_on_startup: None,
                     log_level: None,
+                    copy_workers: None,
                 };
                 fill_default_settings(&mut default_settings);
                 println!(
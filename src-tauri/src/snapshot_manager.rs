@@ -0,0 +1,133 @@
+use crate::structs::{JobInfo, SnapshotInfo};
+use crate::{dedup_manager, drive_manager, manifest_manager, storage_manager};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+// Gets every folder a job's output could be in, including the same folder on other connected drives
+// since drive letters and mount points can change between connections
+fn get_candidate_output_dirs(job_info: &JobInfo) -> Vec<(String, PathBuf)> {
+    let output_dir = PathBuf::from(&job_info.output_dir);
+    let root_drive = drive_manager::get_root_drive(&job_info.output_dir).unwrap_or_default();
+    let relative_output_dir = output_dir
+        .strip_prefix(&root_drive)
+        .map(|path| path.to_path_buf())
+        .unwrap_or_default();
+
+    let mut candidates: Vec<(String, PathBuf)> = vec![(root_drive, output_dir.clone())];
+
+    for drive in drive_manager::get_all_drives() {
+        let candidate = Path::new(&drive[0]).join(&relative_output_dir);
+        if candidate.exists() && !candidates.iter().any(|(_, dir)| *dir == candidate) {
+            candidates.push((drive[0].clone(), candidate));
+        }
+    }
+
+    candidates
+}
+
+fn get_snapshot_info(run_dir: &Path, drive: &str, layout: &str) -> Option<SnapshotInfo> {
+    let recovery_file = run_dir.join("recovery_paths.json");
+    if !recovery_file.exists() {
+        return None;
+    }
+
+    let (created, file_count) = match manifest_manager::read_manifest(run_dir) {
+        Some(manifest) => (manifest.created, manifest.files.len() as u32),
+        None => {
+            // Runs from before manifests were written only have the recovery file
+            let created = std::fs::metadata(&recovery_file)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |duration| duration.as_secs());
+            let file_count = storage_manager::read_json_file::<Vec<Vec<String>>>(
+                recovery_file.to_string_lossy().to_string(),
+            )
+            .map_or(0, |entries| entries.len() as u32);
+            (created, file_count)
+        }
+    };
+
+    Some(SnapshotInfo {
+        path: run_dir.to_string_lossy().to_string(),
+        recovery_file: recovery_file.to_string_lossy().to_string(),
+        drive: drive.to_string(),
+        layout: layout.to_string(),
+        created,
+        file_count,
+    })
+}
+
+// Lists every retained snapshot of a job on connected drives, newest first
+pub fn get_job_snapshots(job_info: &JobInfo) -> Vec<SnapshotInfo> {
+    let mut snapshots: Vec<SnapshotInfo> = Vec::new();
+    let folder_prefix = format!("archway-{}", job_info.uuid);
+
+    for (drive, output_dir) in get_candidate_output_dirs(job_info) {
+        let entries = match std::fs::read_dir(&output_dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+
+        for entry in entries.flatten() {
+            let folder_name = entry.file_name().to_string_lossy().to_string();
+            if !folder_name.starts_with(&folder_prefix)
+                || !entry.file_type().map_or(false, |ft| ft.is_dir())
+            {
+                continue;
+            }
+
+            let run_dir = entry.path();
+            if run_dir.join("snapshots").exists() {
+                for snapshot_dir in dedup_manager::get_snapshot_dirs(&run_dir) {
+                    if let Some(snapshot) = get_snapshot_info(&snapshot_dir, &drive, "dedup") {
+                        snapshots.push(snapshot);
+                    }
+                }
+            } else if let Some(snapshot) = get_snapshot_info(&run_dir, &drive, "mirror") {
+                snapshots.push(snapshot);
+            }
+        }
+    }
+
+    snapshots.sort_by(|a, b| b.created.cmp(&a.created));
+    snapshots
+}
+
+// Rewrites a backup path from a recovery file so it points into the folder the recovery file is in now.
+// Rotating copies renames archway-<uuid>-N folders and drives can be mounted elsewhere,
+// which leaves the absolute paths written at backup time out of date.
+pub fn rebase_backup_path(backup_path: &str, recovery_file: &Path) -> String {
+    let run_dir = match recovery_file.parent() {
+        Some(run_dir) => run_dir,
+        None => return backup_path.to_string(),
+    };
+    // Backup paths in a deduplicated store point into the store folder rather than the snapshot
+    let base_dir = dedup_manager::get_store_dir_from_snapshot(run_dir).unwrap_or(run_dir.to_path_buf());
+
+    // Folder names are archway-<uuid> or archway-<uuid>-N, so match on the archway-<uuid> part
+    let base_name = base_dir
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let folder_prefix: String = base_name.chars().take("archway-".len() + 36).collect();
+    if !folder_prefix.starts_with("archway-") {
+        return backup_path.to_string();
+    }
+
+    let normalized = backup_path.replace('\\', "/");
+    let components: Vec<&str> = normalized.split('/').collect();
+    match components
+        .iter()
+        .position(|component| component.starts_with(&folder_prefix))
+    {
+        Some(index) => {
+            let mut rebased = base_dir.clone();
+            for component in &components[index + 1..] {
+                rebased = rebased.join(component);
+            }
+            rebased.to_string_lossy().to_string()
+        }
+        None => backup_path.to_string(),
+    }
+}
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[derive(Serialize, Deserialize, Clone)]
 pub(crate) struct InputFile {
@@ -11,6 +12,13 @@ pub(crate) struct FilterTraits {
     pub size: Option<u64>,
     pub period: Option<String>,
     pub extensions: Option<Vec<String>>,
+    pub patterns: Option<Vec<String>>,
+    pub min_size: Option<String>,
+    pub max_size: Option<String>,
+    pub duration: Option<String>,
+    pub direction: Option<String>,
+    pub after: Option<String>,
+    pub before: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -19,10 +27,21 @@ pub(crate) struct FileFilter {
     pub traits: FilterTraits,
 }
 
+// A nested group of filters: 'all' and 'any' groups combine their children, 'not' groups
+// negate them, and 'filter' expressions hold a single filter
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct FilterExpression {
+    pub expression_type: String,
+    pub children: Option<Vec<FilterExpression>>,
+    pub filter: Option<FileFilter>,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub(crate) struct TriggerTraits {
     pub time: Option<Vec<String>>,
     pub event: Option<String>,
+    pub catch_up: Option<String>,
+    pub debounce: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -45,6 +64,24 @@ pub(crate) struct JobInfo {
     pub file_filters: Vec<FileFilter>,
     pub triggers: Vec<Trigger>,
     pub version: u32,
+    pub incremental: Option<String>,
+    pub storage_layout: Option<String>,
+    pub filter_expression: Option<FilterExpression>,
+    pub max_total_size: Option<String>,
+    pub respect_gitignore: Option<bool>,
+    pub encryption: Option<String>,
+    pub encryption_salt: Option<String>,
+    pub encryption_keyfile: Option<String>,
+    pub remember_key: Option<bool>,
+    pub output_format: Option<String>,
+    pub archive_volume_size: Option<u64>,
+    pub preserve_ownership: Option<bool>,
+    pub preserve_xattrs: Option<bool>,
+    pub symlink_policy: Option<String>,
+    pub preserve_hardlinks: Option<bool>,
+    pub error_policy: Option<String>,
+    pub file_retries: Option<u32>,
+    pub retry_delay: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -63,4 +100,121 @@ pub(crate) struct JobStatus {
     pub success: bool,
     pub completed: bool,
     pub percent: f32,
+    pub skipped: u32,
+    // Files left out of the run because they could not be copied or verified
+    pub failed_files: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct ManifestEntry {
+    pub source: String,
+    pub output: String,
+    pub size: u64,
+    pub modified: u64,
+    pub hash: Option<String>,
+    // The hash of the output file as stored, when it differs from the source because the file is encrypted
+    pub stored_hash: Option<String>,
+    pub metadata: Option<FileMetadata>,
+    // The target of a symbolic link backed up as a link, which has no contents of its own
+    pub link_target: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct FileTimestamp {
+    pub seconds: i64,
+    pub nanos: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct FileMetadata {
+    pub modified: Option<FileTimestamp>,
+    pub accessed: Option<FileTimestamp>,
+    pub readonly: bool,
+    // Unix permission bits, owner and group
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    // Extended attribute values by name, as hex
+    pub xattrs: Option<HashMap<String, String>>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct RunManifest {
+    pub job_uuid: String,
+    pub created: u64,
+    pub files: Vec<ManifestEntry>,
+    pub libraries: Option<HashMap<String, String>>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct RunJournal {
+    pub job_uuid: String,
+    pub output_dir: String,
+    pub files: Vec<String>,
+    pub created: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct ResumableRun {
+    pub output_dir: String,
+    pub created: u64,
+    pub total_files: u32,
+    pub copied_files: u32,
+    pub verified_files: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct SnapshotInfo {
+    pub path: String,
+    pub recovery_file: String,
+    pub drive: String,
+    pub layout: String,
+    pub created: u64,
+    pub file_count: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct RecoveryOptions {
+    pub files: Option<Vec<String>>,
+    pub include: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
+    pub path_prefix: Option<String>,
+    pub target_prefix_from: Option<String>,
+    pub target_prefix_to: Option<String>,
+    pub remap_libraries: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct RecoveryPreview {
+    pub entries: Vec<Vec<String>>,
+    pub total_size: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct RunEncryption {
+    pub job_uuid: String,
+    pub method: String,
+    pub salt: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct ScrubReport {
+    pub runs_checked: u32,
+    pub files_checked: u64,
+    pub files_unchecked: u64,
+    pub problems: Vec<String>,
+    // Set when the scrub could not run, such as when the output drive is not connected
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct DryRunReport {
+    pub output_dir: String,
+    pub files_to_copy: Vec<String>,
+    pub files_unchanged: Vec<String>,
+    pub total_bytes: u64,
+    pub available_bytes: Option<u64>,
+    pub files_to_delete: Vec<String>,
+    pub folders_to_delete: Vec<String>,
+    pub problems: Vec<String>,
 }
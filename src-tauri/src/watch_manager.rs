@@ -0,0 +1,173 @@
+use crate::job_manager;
+use crate::structs::JobInfo;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Seconds changes must settle for before a watch trigger starts its job, if the trigger does not set one
+const DEFAULT_DEBOUNCE: u64 = 60;
+
+struct JobWatcher {
+    // Kept alive for as long as the folders should be watched
+    _watcher: RecommendedWatcher,
+    dirs: Vec<PathBuf>,
+    debounce: Duration,
+}
+
+static WATCHERS: Lazy<Mutex<HashMap<String, JobWatcher>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+// Time of the latest change in the input folders of each watched job
+static LAST_CHANGES: Lazy<Mutex<HashMap<String, Instant>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn get_watch_debounce(job: &JobInfo) -> Option<Duration> {
+    job.triggers
+        .iter()
+        .find(|trigger| trigger.trigger_type == "watch")
+        .map(|trigger| Duration::from_secs(trigger.traits.debounce.unwrap_or(DEFAULT_DEBOUNCE)))
+}
+
+// Gets the input folders of a job, converting library paths to actual directories
+fn get_watch_dirs(job: &JobInfo) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    for input_dir in &job.input_dirs {
+        if input_dir.path_type == "library" {
+            match job_manager::get_library_dir(&input_dir.path) {
+                Some(library_path) => dirs.push(library_path),
+                None => println!("Unknown library path: {}", input_dir.path),
+            }
+        } else {
+            dirs.push(PathBuf::from(&input_dir.path));
+        }
+    }
+    dirs
+}
+
+fn create_watcher(job: &JobInfo, dirs: &[PathBuf]) -> notify::Result<RecommendedWatcher> {
+    let uuid = job.uuid.clone();
+    let output_dir = PathBuf::from(&job.output_dir);
+
+    let mut watcher = notify::recommended_watcher(move |result: notify::Result<notify::Event>| {
+        let event = match result {
+            Ok(event) => event,
+            Err(e) => {
+                println!("File watch error for job {}: {}", uuid, e);
+                return;
+            }
+        };
+
+        // Reading files is not a change, and changes made by the job itself must not start it again:
+        // its writes to the output folder, and the originals a running move job deletes
+        if matches!(event.kind, EventKind::Access(_)) {
+            return;
+        }
+        if event.paths.iter().all(|path| path.starts_with(&output_dir)) {
+            return;
+        }
+        if matches!(event.kind, EventKind::Remove(_)) && job_manager::is_job_running(&uuid) {
+            return;
+        }
+
+        LAST_CHANGES.lock().unwrap().insert(uuid.clone(), Instant::now());
+    })?;
+
+    for dir in dirs {
+        if let Err(e) = watcher.watch(Path::new(dir), RecursiveMode::Recursive) {
+            println!("Failed to watch folder {}: {}", dir.display(), e);
+        }
+    }
+
+    Ok(watcher)
+}
+
+// Starts watching the input folders of jobs with a watch trigger, and stops watching
+// jobs that were removed or no longer have one
+pub fn sync_watchers(jobs: &[JobInfo]) {
+    let mut watchers = WATCHERS.lock().unwrap();
+
+    for job in jobs {
+        let debounce = match get_watch_debounce(job) {
+            Some(debounce) => debounce,
+            None => continue,
+        };
+        let dirs = get_watch_dirs(job);
+
+        if let Some(job_watcher) = watchers.get_mut(&job.uuid) {
+            job_watcher.debounce = debounce;
+            if job_watcher.dirs == dirs {
+                continue;
+            }
+        }
+
+        match create_watcher(job, &dirs) {
+            Ok(watcher) => {
+                println!("Watching input folders of job {}: {:?}", job.job_name, dirs);
+                watchers.insert(
+                    job.uuid.clone(),
+                    JobWatcher {
+                        _watcher: watcher,
+                        dirs,
+                        debounce,
+                    },
+                );
+            }
+            Err(e) => println!("Failed to create file watcher for job {}: {}", job.job_name, e),
+        }
+    }
+
+    watchers.retain(|uuid, _| {
+        jobs.iter()
+            .any(|job| job.uuid == *uuid && get_watch_debounce(job).is_some())
+    });
+    LAST_CHANGES
+        .lock()
+        .unwrap()
+        .retain(|uuid, _| watchers.contains_key(uuid));
+}
+
+// Gets the watched jobs whose input folders changed and have since settled for the debounce period.
+// Changes made while a job is running stay pending until the run finishes, so the job runs again for them.
+pub fn get_settled_jobs(jobs: &[JobInfo]) -> Vec<JobInfo> {
+    let watchers = WATCHERS.lock().unwrap();
+    let mut last_changes = LAST_CHANGES.lock().unwrap();
+    let mut settled_jobs: Vec<JobInfo> = Vec::new();
+
+    last_changes.retain(|uuid, last_change| {
+        let debounce = match watchers.get(uuid) {
+            Some(job_watcher) => job_watcher.debounce,
+            None => return false,
+        };
+        if last_change.elapsed() < debounce || job_manager::is_job_running(uuid) {
+            return true;
+        }
+        if let Some(job) = jobs.iter().find(|job| job.uuid == *uuid) {
+            settled_jobs.push(job.clone());
+        }
+        false
+    });
+
+    settled_jobs
+}
+
+// Records a change again for a job that could not be started, so it is tried once the changes settle again
+pub fn requeue_change(uuid: &str) {
+    if WATCHERS.lock().unwrap().contains_key(uuid) {
+        LAST_CHANGES.lock().unwrap().insert(uuid.to_string(), Instant::now());
+    }
+}
+
+// Gets the time until the next watched job's changes settle, if any are waiting.
+// Running jobs are left to the regular poll, since their changes wait for the run to finish.
+pub fn get_time_until_settled() -> Option<Duration> {
+    let watchers = WATCHERS.lock().unwrap();
+    let last_changes = LAST_CHANGES.lock().unwrap();
+    last_changes
+        .iter()
+        .filter(|(uuid, _)| !job_manager::is_job_running(uuid))
+        .filter_map(|(uuid, last_change)| {
+            let debounce = watchers.get(uuid)?.debounce;
+            Some(debounce.saturating_sub(last_change.elapsed()))
+        })
+        .min()
+}